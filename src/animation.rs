@@ -0,0 +1,126 @@
+use std::{fs, rc::Rc};
+
+use macroquad::prelude::*;
+use nanoserde::DeJson;
+
+pub const IDLE: &str = "idle";
+pub const WALK: &str = "walk";
+pub const RUN: &str = "run";
+pub const SHOOT: &str = "shoot";
+pub const ENTER_CAR: &str = "enter_car";
+pub const DIE: &str = "die";
+
+/// A run of frames in a sprite sheet, one row per frame.
+#[derive(Debug, Clone, DeJson)]
+pub struct Clip {
+    pub name: String,
+    /// Row of the first frame. The clip has one frame per duration, in the rows below it.
+    pub first: usize,
+    /// Seconds each frame is shown.
+    pub durations: Vec<f32>,
+    /// Starts over after the last frame, otherwise stays on it.
+    #[nserde(default)]
+    pub looping: bool,
+}
+
+/// The clips of one sprite sheet. The first clip is what everything starts out playing.
+#[derive(Debug, DeJson)]
+pub struct AnimationSet {
+    pub clips: Vec<Clip>,
+}
+
+impl AnimationSet {
+    pub fn load(path: &str) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("Failed to read {path}: {e}"))?;
+        let set = AnimationSet::deserialize_json(&text).map_err(|e| format!("Corrupt animations {path}: {e}"))?;
+        if set.clips.is_empty() {
+            return Err(format!("{path} has no clips"));
+        }
+        for clip in &set.clips {
+            if clip.durations.is_empty() || clip.durations.iter().any(|d| *d <= 0.) {
+                return Err(format!("Clip {} in {path} needs positive frame durations", clip.name));
+            }
+        }
+        Ok(set)
+    }
+
+    fn clip(&self, name: &str) -> Option<usize> {
+        self.clips.iter().position(|clip| clip.name == name)
+    }
+}
+
+/// A sprite sheet and the clips laid out in it.
+#[derive(Clone)]
+pub struct SpriteSheet {
+    pub texture: Texture2D,
+    pub animations: Rc<AnimationSet>,
+}
+
+/// Which frame of which clip something is showing.
+pub struct Animation {
+    set: Rc<AnimationSet>,
+    clip: usize,
+    frame: usize,
+    /// Time spent on the current frame.
+    time: f32,
+}
+
+impl Animation {
+    pub fn new(set: Rc<AnimationSet>) -> Self {
+        Animation {
+            set,
+            clip: 0,
+            frame: 0,
+            time: 0.,
+        }
+    }
+
+    pub fn clip_name(&self) -> &str {
+        &self.set.clips[self.clip].name
+    }
+
+    /// Plays the clip called `name` from its first frame. Clips the sheet doesn't have are ignored.
+    pub fn play(&mut self, name: &str) {
+        if let Some(clip) = self.set.clip(name) {
+            self.clip = clip;
+            self.frame = 0;
+            self.time = 0.;
+        }
+    }
+
+    /// Switches to the clip matching what a character is doing, unless it is already playing
+    /// or a clip that only plays once hasn't finished yet.
+    pub fn show(&mut self, name: &str) {
+        if self.clip_name() == name || !(self.set.clips[self.clip].looping || self.is_finished()) {
+            return;
+        }
+        self.play(name);
+    }
+
+    /// Whether a clip that plays once is on its last frame and done showing it.
+    pub fn is_finished(&self) -> bool {
+        let clip = &self.set.clips[self.clip];
+        !clip.looping && self.frame + 1 == clip.durations.len() && self.time >= clip.durations[self.frame]
+    }
+
+    pub fn update(&mut self, dt: f32) {
+        let clip = &self.set.clips[self.clip];
+        self.time += dt;
+        while self.time >= clip.durations[self.frame] {
+            if self.frame + 1 < clip.durations.len() {
+                self.time -= clip.durations[self.frame];
+                self.frame += 1;
+            } else if clip.looping {
+                self.time -= clip.durations[self.frame];
+                self.frame = 0;
+            } else {
+                break;
+            }
+        }
+    }
+
+    /// Row of the sprite sheet the current frame is in.
+    pub fn row(&self) -> usize {
+        self.set.clips[self.clip].first + self.frame
+    }
+}
@@ -0,0 +1,285 @@
+use std::{
+    collections::HashMap,
+    fs,
+    marker::PhantomData,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+#[cfg(feature = "audio")]
+use macroquad::audio::{load_sound_from_bytes, Sound};
+use macroquad::prelude::*;
+use nanoserde::DeJson;
+
+/// Lists the assets by name. Paths in it and in data files are relative to the directory it is in.
+pub const MANIFEST: &str = "assets/manifest.json";
+/// Seconds between looking for changed files.
+const RELOAD_INTERVAL: f64 = 0.5;
+
+#[derive(DeJson)]
+struct ManifestFile {
+    #[nserde(default)]
+    textures: HashMap<String, String>,
+    #[nserde(default)]
+    sounds: HashMap<String, String>,
+    /// Data files, or directories of them, read by whatever needs them.
+    #[nserde(default)]
+    data: HashMap<String, String>,
+}
+
+/// The parsed manifest, enough to find the data files without loading any textures or sounds.
+pub struct Manifest {
+    dir: PathBuf,
+    files: ManifestFile,
+}
+
+impl Manifest {
+    pub fn load(path: &str) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("Failed to read {path}: {e}"))?;
+        let files =
+            ManifestFile::deserialize_json(&text).map_err(|e| format!("Corrupt asset manifest {path}: {e}"))?;
+        Ok(Manifest {
+            dir: Path::new(path).parent().map(Path::to_path_buf).unwrap_or_default(),
+            files,
+        })
+    }
+
+    /// Where a file named in the manifest or in a data file is.
+    pub fn path(&self, file: &str) -> PathBuf {
+        self.dir.join(file)
+    }
+
+    /// Path of the data called `name`.
+    pub fn data_path(&self, name: &str) -> Result<String, String> {
+        let file = self.files.data.get(name).ok_or_else(|| format!("No data called {name}"))?;
+        self.path(file)
+            .to_str()
+            .map(str::to_string)
+            .ok_or_else(|| format!("Data {name} has a path that isn't valid UTF-8"))
+    }
+}
+
+/// Refers to an asset of the manager it came from, and stays valid when the asset is reloaded.
+pub struct Handle<T> {
+    index: usize,
+    kind: PhantomData<T>,
+}
+
+impl<T> Clone for Handle<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for Handle<T> {}
+
+struct Entry<T> {
+    name: String,
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    asset: T,
+}
+
+impl<T> Entry<T> {
+    fn new(name: &str, path: PathBuf, asset: T) -> Self {
+        Entry {
+            name: name.to_string(),
+            modified: modified(&path),
+            path,
+            asset,
+        }
+    }
+
+    /// Whether the file changed since the last call.
+    fn changed(&mut self) -> bool {
+        let modified = modified(&self.path);
+        if modified == self.modified {
+            return false;
+        }
+        self.modified = modified;
+        true
+    }
+}
+
+/// Textures, sounds and data files from the manifest, handed out by name or handle.
+/// In debug builds changed files are loaded again while the game runs.
+pub struct Assets {
+    textures: Vec<Entry<Texture2D>>,
+    #[cfg(feature = "audio")]
+    sounds: Vec<Entry<Sound>>,
+    /// Paths of data files and directories, read by the loaders of whatever they hold.
+    data: Vec<Entry<PathBuf>>,
+    manifest: Manifest,
+    last_check: f64,
+}
+
+impl Assets {
+    /// Loads everything in the manifest at `path`. Sounds are skipped without the `audio` feature.
+    pub async fn load(path: &str) -> Result<Self, String> {
+        let manifest = Manifest::load(path)?;
+        let mut assets = Assets {
+            textures: Vec::new(),
+            #[cfg(feature = "audio")]
+            sounds: Vec::new(),
+            data: Vec::new(),
+            manifest,
+            last_check: get_time(),
+        };
+        let manifest = &assets.manifest;
+        for (name, file) in sorted(&manifest.files.textures) {
+            let path = manifest.path(file);
+            let texture = load_texture_file(&path)?;
+            assets.textures.push(Entry::new(name, path, texture));
+        }
+        #[cfg(feature = "audio")]
+        for (name, file) in sorted(&manifest.files.sounds) {
+            let path = manifest.path(file);
+            let sound = load_sound_file(&path).await?;
+            assets.sounds.push(Entry::new(name, path, sound));
+        }
+        #[cfg(not(feature = "audio"))]
+        if !manifest.files.sounds.is_empty() {
+            println!("built without the audio feature, not loading {} sounds", manifest.files.sounds.len());
+        }
+        for (name, file) in sorted(&manifest.files.data) {
+            let path = manifest.path(file);
+            if !path.exists() {
+                return Err(format!("Data {name} not found at {}", path.display()));
+            }
+            assets.data.push(Entry::new(name, path.clone(), path));
+        }
+        Ok(assets)
+    }
+
+    pub fn texture_handle(&self, name: &str) -> Result<Handle<Texture2D>, String> {
+        find(&self.textures, name).ok_or_else(|| format!("No texture called {name}"))
+    }
+
+    pub fn texture(&self, handle: Handle<Texture2D>) -> &Texture2D {
+        &self.textures[handle.index].asset
+    }
+
+    /// Loads a texture that isn't in the manifest, like the sprites named in vehicle files.
+    /// Loading the same file again gives the same texture.
+    pub fn load_texture(&mut self, file: &str) -> Result<Texture2D, String> {
+        let path = self.manifest.path(file);
+        if let Some(entry) = self.textures.iter().find(|entry| entry.path == path) {
+            return Ok(entry.asset.clone());
+        }
+        let texture = load_texture_file(&path)?;
+        self.textures.push(Entry::new(file, path, texture.clone()));
+        Ok(texture)
+    }
+
+    #[cfg(feature = "audio")]
+    pub fn sound_handle(&self, name: &str) -> Result<Handle<Sound>, String> {
+        find(&self.sounds, name).ok_or_else(|| format!("No sound called {name}"))
+    }
+
+    #[cfg(feature = "audio")]
+    pub fn sound(&self, handle: Handle<Sound>) -> &Sound {
+        &self.sounds[handle.index].asset
+    }
+
+    /// Path of the data called `name`, for the loaders that read the files themselves.
+    pub fn data_path(&self, name: &str) -> Result<&str, String> {
+        let entry = self.data.iter().find(|entry| entry.name == name);
+        let path = entry.ok_or_else(|| format!("No data called {name}"))?;
+        path.asset
+            .to_str()
+            .ok_or_else(|| format!("Data {name} has a path that isn't valid UTF-8"))
+    }
+
+    /// Loads the files changed since they were last loaded, at most every `RELOAD_INTERVAL`.
+    /// Textures that keep their size are updated in place, so every copy of them shows the change.
+    /// Data is only read by whoever uses it, so the names of the changed data are returned for them to reload.
+    pub async fn hot_reload(&mut self) -> Vec<String> {
+        if get_time() - self.last_check < RELOAD_INTERVAL {
+            return Vec::new();
+        }
+        self.last_check = get_time();
+
+        for entry in &mut self.textures {
+            if !entry.changed() {
+                continue;
+            }
+            match reload_texture(&entry.asset, &entry.path) {
+                Ok(()) => println!("reloaded {}", entry.path.display()),
+                Err(e) => println!("{e}"),
+            }
+        }
+        #[cfg(feature = "audio")]
+        for entry in &mut self.sounds {
+            if !entry.changed() {
+                continue;
+            }
+            match load_sound_file(&entry.path).await {
+                Ok(sound) => {
+                    entry.asset = sound;
+                    println!("reloaded {}", entry.path.display());
+                }
+                Err(e) => println!("{e}"),
+            }
+        }
+        self.data
+            .iter_mut()
+            .filter_map(|entry| entry.changed().then(|| entry.name.clone()))
+            .collect()
+    }
+}
+
+fn find<T>(entries: &[Entry<T>], name: &str) -> Option<Handle<T>> {
+    let index = entries.iter().position(|entry| entry.name == name)?;
+    Some(Handle {
+        index,
+        kind: PhantomData,
+    })
+}
+
+/// Manifest entries by name, so assets always load in the same order.
+fn sorted(entries: &HashMap<String, String>) -> Vec<(&String, &String)> {
+    let mut entries: Vec<_> = entries.iter().collect();
+    entries.sort();
+    entries
+}
+
+/// When the file, or the newest file in the directory, was last changed.
+fn modified(path: &Path) -> Option<SystemTime> {
+    let own = fs::metadata(path).and_then(|m| m.modified()).ok();
+    if !path.is_dir() {
+        return own;
+    }
+    let newest = fs::read_dir(path)
+        .ok()?
+        .filter_map(|entry| entry.ok()?.metadata().ok()?.modified().ok())
+        .max();
+    own.max(newest)
+}
+
+fn read_image(path: &Path) -> Result<Image, String> {
+    let bytes = fs::read(path).map_err(|e| format!("Failed to read {}: {e}", path.display()))?;
+    Image::from_file_with_format(&bytes, None).map_err(|e| format!("Corrupt image {}: {e}", path.display()))
+}
+
+fn load_texture_file(path: &Path) -> Result<Texture2D, String> {
+    let texture = Texture2D::from_image(&read_image(path)?);
+    texture.set_filter(FilterMode::Nearest);
+    Ok(texture)
+}
+
+fn reload_texture(texture: &Texture2D, path: &Path) -> Result<(), String> {
+    let image = read_image(path)?;
+    if texture.size() != Vec2::new(image.width as f32, image.height as f32) {
+        return Err(format!("{} changed size, restart the game to see it", path.display()));
+    }
+    texture.update(&image);
+    Ok(())
+}
+
+#[cfg(feature = "audio")]
+async fn load_sound_file(path: &Path) -> Result<Sound, String> {
+    let bytes = fs::read(path).map_err(|e| format!("Failed to read {}: {e}", path.display()))?;
+    load_sound_from_bytes(&bytes)
+        .await
+        .map_err(|e| format!("Corrupt sound {}: {e}", path.display()))
+}
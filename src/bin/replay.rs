@@ -0,0 +1,27 @@
+//! Replays a recorded session without opening a window and checks that it ends up
+//! exactly where it did when it was recorded.
+//!
+//! Usage: `cargo run --bin replay -- replays/replay0.json`
+
+use autotheft2d::{
+    assets::{Manifest, MANIFEST},
+    replay::Recording,
+};
+
+fn main() {
+    let Some(path) = std::env::args().nth(1) else {
+        eprintln!("usage: replay <recording.json>");
+        std::process::exit(2);
+    };
+    let result = Recording::load(&path).and_then(|recording| {
+        recording.verify(&Manifest::load(MANIFEST)?)?;
+        Ok(recording)
+    });
+    match result {
+        Ok(recording) => println!("{path}: {} ticks replayed identically", recording.ticks),
+        Err(e) => {
+            eprintln!("{path}: {e}");
+            std::process::exit(1);
+        }
+    }
+}
@@ -57,6 +57,71 @@ impl LineSegment {
             None
         }
     }
+
+    /// Distance from `point` to the closest point on the segment.
+    pub fn distance_to(&self, point: Vec2) -> f32 {
+        let along = self.end - self.start;
+        let t = ((point - self.start).dot(along) / along.length_squared().max(f32::EPSILON)).clamp(0., 1.);
+        point.distance(self.start + along * t)
+    }
+}
+
+/// An area that doesn't block anything, it only tells what is inside it.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Shape {
+    Rect(Rect),
+    Circle(Circle),
+    /// Corners in order, either way round.
+    Polygon(Vec<Vec2>),
+}
+
+impl Shape {
+    /// Whether a circle around `center` touches the shape. A `radius` of 0 tests a single point.
+    pub fn overlaps_circle(&self, center: Vec2, radius: f32) -> bool {
+        match self {
+            Shape::Rect(rect) => {
+                let closest = center.clamp(rect.point(), rect.point() + rect.size());
+                closest.distance(center) <= radius
+            }
+            Shape::Circle(circle) => center.distance(circle.point()) <= circle.r + radius,
+            Shape::Polygon(corners) => {
+                polygon_contains(corners, center) || polygon_edges(corners).any(|edge| edge.distance_to(center) <= radius)
+            }
+        }
+    }
+
+    pub fn draw_outline(&self, offset: Vec2, thickness: f32, color: Color) {
+        match self {
+            Shape::Rect(rect) => draw_rectangle_lines(rect.x + offset.x, rect.y + offset.y, rect.w, rect.h, thickness, color),
+            Shape::Circle(circle) => draw_circle_lines(circle.x + offset.x, circle.y + offset.y, circle.r, thickness, color),
+            Shape::Polygon(corners) => {
+                for edge in polygon_edges(corners) {
+                    let (start, end) = (edge.start + offset, edge.end + offset);
+                    draw_line(start.x, start.y, end.x, end.y, thickness, color);
+                }
+            }
+        }
+    }
+}
+
+fn polygon_edges(corners: &[Vec2]) -> impl Iterator<Item = LineSegment> + '_ {
+    corners
+        .iter()
+        .zip(corners.iter().cycle().skip(1))
+        .map(|(start, end)| LineSegment::new(*start, *end))
+}
+
+/// Even-odd rule: a point is inside if a ray from it crosses the outline an odd number of times.
+fn polygon_contains(corners: &[Vec2], point: Vec2) -> bool {
+    polygon_edges(corners)
+        .filter(|edge| {
+            (edge.start.y > point.y) != (edge.end.y > point.y)
+                && point.x
+                    < edge.start.x + (point.y - edge.start.y) / (edge.end.y - edge.start.y) * (edge.end.x - edge.start.x)
+        })
+        .count()
+        % 2
+        == 1
 }
 
 // Implementation for Macroquad's Rect struct
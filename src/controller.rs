@@ -1,17 +1,65 @@
 use macroquad::prelude::*;
+use nanoserde::{DeJson, SerJson};
 
-use crate::{player::Player, Direction};
+use crate::{
+    input::{Action, Bindings},
+    Direction,
+};
 
-pub fn handle_key_inputs(character: &mut Player) {
-    let mut state = ControllerDirectionState::default();
-    state.update_state();
-    if let Some(facing) = state.get_facing() {
-        character.facing = facing;
-        character.pos_add(facing.as_vector() * character.movement_speed * get_frame_time());
+/// How the direction the player holds is turned into driving.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, SerJson, DeJson)]
+pub enum ControlScheme {
+    /// Relative to the vehicle: up is gas, down is brake and reverse, left and right steer.
+    #[default]
+    Tank,
+    /// Relative to the screen: the vehicle steers toward the held direction.
+    ScreenRelative,
+}
+
+impl ControlScheme {
+    pub fn next(&self) -> Self {
+        match self {
+            ControlScheme::Tank => ControlScheme::ScreenRelative,
+            ControlScheme::ScreenRelative => ControlScheme::Tank,
+        }
+    }
+}
+
+/// Continuous input from a gamepad, with the deadzones already applied.
+#[derive(Debug, Clone, Copy, Default, PartialEq, SerJson, DeJson)]
+pub struct AnalogState {
+    /// Left stick, y pointing down like the world. Steers a vehicle or walks in any direction.
+    pub stick: [f32; 2],
+    /// Both triggers from 0 to 1.
+    pub throttle: f32,
+    pub brake: f32,
+}
+
+impl AnalogState {
+    pub fn stick(&self) -> Vec2 {
+        Vec2::from(self.stick)
+    }
+}
+
+/// Ignores stick movement within `deadzone` of the center and rescales the rest,
+/// so the output still goes smoothly from 0 to 1.
+pub fn apply_deadzone(stick: Vec2, deadzone: f32) -> Vec2 {
+    let length = stick.length();
+    if length <= deadzone {
+        return Vec2::ZERO;
+    }
+    stick / length * ((length - deadzone) / (1. - deadzone)).min(1.)
+}
+
+/// The same for a trigger, going from 0 to 1.
+pub fn apply_trigger_deadzone(value: f32, deadzone: f32) -> f32 {
+    if value <= deadzone {
+        return 0.;
     }
+    ((value - deadzone) / (1. - deadzone)).min(1.)
 }
 
-#[derive(Default)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, SerJson, DeJson)]
 pub struct ControllerDirectionState {
     pub up: bool,
     pub left: bool,
@@ -20,32 +68,18 @@ pub struct ControllerDirectionState {
 }
 
 impl ControllerDirectionState {
-    pub fn handle_key_inputs(&mut self, player: &mut Player) {
-        self.update_state();
-        
-        player.handle_controls(self.get_facing());
+    /// The directions whose bound keys are currently held down.
+    pub fn from_bindings(bindings: &Bindings) -> Self {
+        let mut state = Self::default();
+        state.update_state(bindings);
+        state
     }
-    fn update_state(&mut self) {
-        if is_key_down(KeyCode::W) {
-           self.up = true;
-        } else {
-            self.up = false;
-        }
-        if is_key_down(KeyCode::A) {
-            self.left = true;
-        } else {
-            self.left = false;
-        }
-        if is_key_down(KeyCode::S) {
-            self.down = true;
-        } else {
-            self.down = false;
-        }
-        if is_key_down(KeyCode::D) {
-            self.right = true;
-        } else {
-            self.right = false;
-        }
+
+    fn update_state(&mut self, bindings: &Bindings) {
+        self.up = bindings.is_down(Action::MoveUp);
+        self.left = bindings.is_down(Action::MoveLeft);
+        self.down = bindings.is_down(Action::MoveDown);
+        self.right = bindings.is_down(Action::MoveRight);
     } 
     
     pub fn get_facing(&self) -> Option<Direction> {
@@ -0,0 +1,81 @@
+use std::{cell::RefCell, rc::Rc};
+
+use macroquad::prelude::*;
+
+use crate::{trigger::ZoneEvent, vehicle::{Seat, Vehicle}, wanted::Crime};
+
+/// Something in the world that can be hurt, destroyed or stand in a trigger zone.
+#[derive(Clone)]
+pub enum Entity {
+    /// The player, on foot or in a vehicle.
+    Player,
+    /// A pedestrian of the crowd, by id.
+    Pedestrian(u32),
+    Vehicle(Rc<RefCell<Vehicle>>),
+}
+
+impl PartialEq for Entity {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Entity::Player, Entity::Player) => true,
+            (Entity::Pedestrian(a), Entity::Pedestrian(b)) => a == b,
+            (Entity::Vehicle(a), Entity::Vehicle(b)) => Rc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+}
+
+/// Something that happened during a tick, for whoever is interested.
+#[derive(Clone)]
+pub enum GameEvent {
+    VehicleEntered { vehicle: Rc<RefCell<Vehicle>>, seat: Seat },
+    VehicleExited { vehicle: Rc<RefCell<Vehicle>> },
+    ShotFired { from: Vec2, direction: Vec2, by_player: bool },
+    EntityDamaged { entity: Entity, amount: f32 },
+    EntityDestroyed { entity: Entity },
+    CrimeCommitted { crime: Crime, pos: Vec2 },
+    WantedLevelChanged { stars: u8 },
+    MissionStarted { id: String, title: String },
+    /// The next objective of the active mission is up.
+    ObjectiveStarted { text: String },
+    MissionCompleted { id: String, reward: u32 },
+    MissionFailed { id: String, reason: String },
+    Zone(ZoneEvent),
+}
+
+pub type Subscriber = Box<dyn FnMut(&GameEvent)>;
+
+/// Events published during the current tick, handed to the subscribers at its end.
+#[derive(Default)]
+pub struct EventBus {
+    queue: Vec<GameEvent>,
+    subscribers: Vec<Subscriber>,
+}
+
+impl EventBus {
+    pub fn publish(&mut self, event: GameEvent) {
+        self.queue.push(event);
+    }
+
+    /// Has `subscriber` called with every event from now on.
+    /// Subscribers only get to look, anything that needs the world reads the events `dispatch` returns.
+    pub fn subscribe(&mut self, subscriber: impl FnMut(&GameEvent) + 'static) {
+        self.subscribers.push(Box::new(subscriber));
+    }
+
+    /// Events published so far this tick.
+    pub fn pending(&self) -> &[GameEvent] {
+        &self.queue
+    }
+
+    /// Passes the events published since the last dispatch to every subscriber, in order, and returns them.
+    pub fn dispatch(&mut self) -> Vec<GameEvent> {
+        let events = std::mem::take(&mut self.queue);
+        for subscriber in &mut self.subscribers {
+            for event in &events {
+                subscriber(event);
+            }
+        }
+        events
+    }
+}
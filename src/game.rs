@@ -0,0 +1,339 @@
+// Clippy flags the code `DeJson` derives for `TickInput`'s `Option` fields,
+// and lint levels set on the struct don't carry over to that impl.
+#![allow(clippy::question_mark)]
+
+use std::rc::Rc;
+
+use macroquad::prelude::*;
+use nanoserde::{DeJson, SerJson};
+
+use crate::{
+    animation::{self, AnimationSet, SpriteSheet},
+    assets::Manifest,
+    controller::{AnalogState, ControllerDirectionState},
+    draw::Draw,
+    map::Map,
+    event::{Entity, GameEvent},
+    mission::Missions,
+    player::{EntryEvent, Player, ENTER_RANGE},
+    save::SaveGame,
+    script::Scripts,
+    vehicle::{
+        catalogue::VehicleCatalogue,
+        Seat, Vehicle,
+    },
+    wanted::Crime,
+    weapons::{Weapon, WeaponKind},
+    Update, World,
+};
+
+/// The locked car parked next to the player at the start of a new game.
+const STARTING_VEHICLE: &str = "sports_car";
+
+/// The simulation always advances in steps of this many seconds, however long a frame takes.
+pub const TICK: f32 = 1. / 60.;
+
+/// Everything the player did during one tick.
+#[derive(Debug, Clone, Copy, Default, PartialEq, SerJson, DeJson)]
+pub struct TickInput {
+    pub direction: ControllerDirectionState,
+    /// Gamepad sticks and triggers, replacing `direction` while a gamepad is in use.
+    pub analog: Option<AnalogState>,
+    /// Direction of the shot fired this tick, if any.
+    pub fire: Option<[f32; 2]>,
+    /// Get into or out of a vehicle.
+    pub use_vehicle: bool,
+    /// Get into a passenger seat of a vehicle, or out of it.
+    #[nserde(default)]
+    pub board_as_passenger: bool,
+    #[nserde(default)]
+    pub handbrake: bool,
+    #[nserde(default)]
+    pub shift_up: bool,
+    #[nserde(default)]
+    pub shift_down: bool,
+    /// Switch to the next weapon.
+    #[nserde(default)]
+    pub next_weapon: bool,
+    #[nserde(default)]
+    pub switch_control_scheme: bool,
+}
+
+impl TickInput {
+    /// Forgets the one-off actions once they were handled, keeping only what is held down.
+    pub fn clear_actions(&mut self) {
+        self.fire = None;
+        self.use_vehicle = false;
+        self.board_as_passenger = false;
+        self.shift_up = false;
+        self.shift_down = false;
+        self.next_weapon = false;
+        self.switch_control_scheme = false;
+    }
+}
+
+/// Sprites new entities are created with.
+#[derive(Clone)]
+pub struct Sprites {
+    pub tile_map: Texture2D,
+    /// Shared by the player, pedestrians and officers.
+    pub player: SpriteSheet,
+    pub vehicles: VehicleCatalogue,
+    pub weapon: Texture2D,
+}
+
+impl Sprites {
+    /// Sprites that don't need a window, for running the simulation headless.
+    /// The vehicle models and animations still come from the data files in `manifest`.
+    pub fn placeholder(manifest: &Manifest) -> Result<Self, String> {
+        let texture = Texture2D::from_miniquad_texture(macroquad::miniquad::TextureId::from_raw_id(
+            macroquad::miniquad::RawId::OpenGl(0),
+        ));
+        Ok(Sprites {
+            tile_map: texture.clone(),
+            player: SpriteSheet {
+                texture: texture.clone(),
+                animations: Rc::new(AnimationSet::load(&manifest.data_path("animations")?)?),
+            },
+            vehicles: VehicleCatalogue::load(&manifest.data_path("vehicles")?, |_| Ok(texture.clone()))?,
+            weapon: texture,
+        })
+    }
+}
+
+/// The simulated part of a session: the world and the player in it.
+/// Given the same seed and the same inputs it always ends up in the same state.
+pub struct Game {
+    pub world: World,
+    pub player: Player,
+    pub missions: Missions,
+    pub scripts: Scripts,
+    /// Ticks simulated so far.
+    pub ticks: u64,
+}
+
+impl Game {
+    pub fn new(map: Map, seed: u64, sprites: &Sprites, missions: Missions, scripts: Scripts) -> Self {
+        let mut world = World::new(map, seed);
+        add_spawn_textures(&mut world, sprites);
+        match world.spawn_vehicle(STARTING_VEHICLE, Vec2::ZERO, Vec2::NEG_Y) {
+            Ok(vehicle) => vehicle.borrow_mut().locked = true,
+            Err(e) => println!("{e}"),
+        }
+        let mut player = Player::new(sprites.player.clone());
+        player.weapons.push(Weapon::new(WeaponKind::Pistol, sprites.weapon.clone()));
+        Game {
+            world,
+            player,
+            missions,
+            scripts,
+            ticks: 0,
+        }
+    }
+
+    pub fn from_save(
+        save: &SaveGame,
+        seed: u64,
+        sprites: &Sprites,
+        missions: Missions,
+        mut scripts: Scripts,
+    ) -> Result<Self, String> {
+        let mut player = Player::new(sprites.player.clone());
+        let mut world = save.restore(sprites, seed, &mut player)?;
+        add_spawn_textures(&mut world, sprites);
+        scripts.restore(&save.scripts)?;
+        Ok(Game {
+            world,
+            player,
+            missions,
+            scripts,
+            ticks: 0,
+        })
+    }
+
+    /// Advances the simulation by one `TICK`. Must not depend on anything but `input`,
+    /// so replays come out the same.
+    pub fn tick(&mut self, input: &TickInput) {
+        let world = &mut self.world;
+        let player = &mut self.player;
+        let health_before = player.health + player.armor;
+        self.scripts.update(world, player, TICK);
+        if input.switch_control_scheme {
+            player.control_scheme = player.control_scheme.next();
+            println!("control scheme: {:?}", player.control_scheme);
+        }
+        match &input.analog {
+            Some(analog) => player.handle_analog(analog, TICK),
+            None => player.handle_controls(input.direction.get_facing(), TICK),
+        }
+
+        if input.use_vehicle || input.board_as_passenger {
+            if let Some(vehicle) = player.in_vehicle.clone() {
+                player.leave_vehicle(vehicle.clone());
+                world.events.publish(GameEvent::VehicleExited { vehicle });
+            } else if player.entering.is_some() {
+                player.cancel_entering();
+            } else if let Some((vehicle, door)) = world.nearest_door(player.pos, ENTER_RANGE) {
+                if !input.board_as_passenger {
+                    player.start_entering(vehicle, door, Seat::Driver);
+                } else if let Some(seat) = vehicle.borrow().free_passenger_seat(door) {
+                    player.start_entering(vehicle.clone(), Vehicle::seat_door(seat), seat);
+                } else {
+                    println!("no free seat");
+                }
+            }
+        }
+        if let Some(vehicle) = player.driving() {
+            let mut vehicle = vehicle.borrow_mut();
+            vehicle.handbrake = input.handbrake;
+            // Shifting past the highest or lowest gear just does nothing
+            if input.shift_up {
+                let _ = vehicle.gearbox.shift_up();
+            }
+            if input.shift_down {
+                let _ = vehicle.gearbox.shift_down();
+            }
+        }
+        if input.next_weapon && !player.weapons.is_empty() {
+            player.current_weapon = (player.current_weapon + 1) % player.weapons.len();
+        }
+        match world.update_vehicle_entry(player, TICK) {
+            Some(EntryEvent::Entered(vehicle)) => {
+                world.events.publish(GameEvent::VehicleEntered {
+                    vehicle,
+                    seat: player.seat,
+                });
+            }
+            Some(EntryEvent::WindowBroken(_) | EntryEvent::DriverPulledOut(_)) => {
+                world.report_crime(Crime::VehicleTheft, player.pos);
+            }
+            None => {}
+        }
+
+        let player_pos = player.pos;
+        let (in_vehicle, seat) = (player.in_vehicle.clone(), player.seat);
+        let mut fired = false;
+        if let Some(weapon) = player.weapon_mut() {
+            let mut aim = input.fire.map(Vec2::from);
+            match &in_vehicle {
+                // Drive-bys only work out of the side windows, and only with guns small enough to handle in a car
+                Some(vehicle) => {
+                    let vehicle = vehicle.borrow();
+                    weapon.pos = vehicle.door_position(Vehicle::seat_door(seat));
+                    aim = aim
+                        .filter(|_| weapon.kind.drive_by())
+                        .map(|aim| vehicle.aim_from_window(seat, aim));
+                }
+                None => {
+                    weapon.pos = player_pos;
+                    weapon.pos.y += 30.;
+                }
+            }
+            if let Some(direction) = aim.filter(|_| weapon.ammo > 0) {
+                let cover = weapon
+                    .shoot(direction, &world.collideables, in_vehicle.as_ref())
+                    .map(|(_, distance)| distance);
+                world.events.publish(GameEvent::ShotFired {
+                    from: weapon.pos,
+                    direction,
+                    by_player: true,
+                });
+                world.shoot_pedestrians(weapon.pos, direction, weapon.kind.damage(), cover);
+                world.crowd.alert_gunshot(player_pos);
+                world.report_crime(Crime::ShotFired, player_pos);
+                fired = true;
+            }
+        }
+        if fired {
+            player.animation.play(animation::SHOOT);
+        }
+
+        player.update(TICK);
+        world.update_intersections(player.driving(), TICK);
+        let boarding = player
+            .entering
+            .as_ref()
+            .filter(|entry| entry.seat != Seat::Driver)
+            .map(|entry| &entry.vehicle);
+        world.update_traffic(player.pos, player.in_vehicle.is_none(), boarding);
+        let player_on_foot = player.in_vehicle.is_none().then_some(player.pos);
+        let crowd_events = world.update_crowd(player.pos, player_on_foot, TICK);
+        player.take_damage(crowd_events.damage_to_player);
+        if let Some(vehicle) = player.driving() {
+            for _ in crowd_events.knockdowns.iter().filter(|k| Rc::ptr_eq(&k.vehicle, vehicle)) {
+                world.report_crime(Crime::PedestrianHit, player.pos);
+            }
+        }
+        let police_update = world.update_police(player.pos, player.in_vehicle.as_ref(), TICK);
+        player.take_damage(police_update.damage_to_player);
+        for vehicle in &world.vehicles {
+            vehicle.borrow_mut().update(TICK);
+        }
+        world.resolve_vehicle_collisions();
+        world.update_triggers(player.pos);
+        if player.health + player.armor < health_before {
+            world.events.publish(GameEvent::EntityDamaged {
+                entity: Entity::Player,
+                amount: health_before - player.health - player.armor,
+            });
+            if player.health <= 0. {
+                world.events.publish(GameEvent::EntityDestroyed { entity: Entity::Player });
+            }
+        }
+        self.missions.update(world, player, TICK);
+        for event in world.events.dispatch() {
+            self.scripts.handle_event(world, player, &event);
+        }
+        self.ticks += 1;
+    }
+
+    /// Fingerprint of the simulation state, to tell whether two runs ended up in the same place.
+    /// 64 bit FNV-1a over little endian bytes, so it is the same for every build and platform.
+    pub fn checksum(&self) -> u64 {
+        let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+        let mut write = |bytes: &[u8]| {
+            for byte in bytes {
+                hash ^= *byte as u64;
+                hash = hash.wrapping_mul(0x0100_0000_01b3);
+            }
+        };
+        write(&self.ticks.to_le_bytes());
+        let mut hash_vec2 = |v: Vec2| {
+            write(&v.x.to_le_bytes());
+            write(&v.y.to_le_bytes());
+        };
+        hash_vec2(self.player.pos);
+        hash_vec2(Vec2::new(self.player.health, self.player.money as f32));
+        for vehicle in &self.world.vehicles {
+            let vehicle = vehicle.borrow();
+            hash_vec2(vehicle.position());
+            hash_vec2(vehicle.velocity_vector());
+            hash_vec2(vehicle.rotation);
+            hash_vec2(Vec2::new(vehicle.damage, vehicle.rpm));
+        }
+        for pedestrian in &self.world.crowd.pedestrians {
+            hash_vec2(pedestrian.pos);
+        }
+        for officer in &self.world.police.officers {
+            hash_vec2(officer.body.pos);
+        }
+        write(&[self.world.wanted.stars()]);
+        hash
+    }
+}
+
+/// Gives the world the sprites and vehicle models it spawns traffic, pedestrians and police with.
+pub fn add_spawn_textures(world: &mut World, sprites: &Sprites) {
+    world.vehicle_catalogue = sprites.vehicles.clone();
+    for model in sprites.vehicles.models() {
+        if model.traffic {
+            world.traffic.vehicle_models.push(model.name.clone());
+        }
+        if model.police {
+            world.police.vehicle_models.push(model.name.clone());
+        }
+    }
+    world.crowd.sprites.push(sprites.player.clone());
+    world.police.officer_sprites.push(sprites.player.clone());
+    world.weapon_sprite = Some(sprites.weapon.clone());
+}
@@ -0,0 +1,96 @@
+use gilrs::{Axis, Button, EventType, GamepadId, Gilrs};
+use macroquad::prelude::*;
+
+use crate::{
+    controller::{apply_deadzone, apply_trigger_deadzone, AnalogState},
+    input::{Action, Bindings},
+};
+
+/// Reads the gamepad that was used last.
+pub struct Gamepads {
+    gilrs: Gilrs,
+    active: Option<GamepadId>,
+    /// Buttons pressed since the last `poll`.
+    pressed: Vec<Button>,
+}
+
+impl Gamepads {
+    pub fn new() -> Result<Self, String> {
+        let gilrs = Gilrs::new().map_err(|e| format!("Failed to open gamepads: {e}"))?;
+        Ok(Gamepads {
+            gilrs,
+            active: None,
+            pressed: Vec::new(),
+        })
+    }
+
+    /// Handles what happened on the gamepads since the last frame. Call once per frame.
+    pub fn poll(&mut self) {
+        self.pressed.clear();
+        while let Some(event) = self.gilrs.next_event() {
+            match event.event {
+                EventType::ButtonPressed(button, _) => {
+                    self.active = Some(event.id);
+                    self.pressed.push(button);
+                }
+                EventType::AxisChanged(..) | EventType::ButtonChanged(..) => self.active = Some(event.id),
+                EventType::Disconnected if self.active == Some(event.id) => self.active = None,
+                _ => {}
+            }
+        }
+    }
+
+    /// Sticks and triggers of the active gamepad, if there is one.
+    pub fn analog(&self, bindings: &Bindings) -> Option<AnalogState> {
+        let gamepad = self.gilrs.connected_gamepad(self.active?)?;
+        // Up is positive on the gamepad, but negative in the world
+        let stick = Vec2::new(gamepad.value(Axis::LeftStickX), -gamepad.value(Axis::LeftStickY));
+        let trigger = |button| {
+            let value = gamepad.button_data(button).map_or(0., |data| data.value());
+            apply_trigger_deadzone(value, bindings.trigger_deadzone)
+        };
+        Some(AnalogState {
+            stick: apply_deadzone(stick, bindings.stick_deadzone).into(),
+            throttle: trigger(Button::RightTrigger2),
+            brake: trigger(Button::LeftTrigger2),
+        })
+    }
+
+    pub fn is_pressed(&self, action: Action) -> bool {
+        button(action).is_some_and(|button| self.pressed.contains(&button))
+    }
+
+    pub fn is_down(&self, action: Action) -> bool {
+        let gamepad = self.active.and_then(|id| self.gilrs.connected_gamepad(id));
+        match (gamepad, button(action)) {
+            (Some(gamepad), Some(button)) => gamepad.is_pressed(button),
+            _ => false,
+        }
+    }
+}
+
+/// The gamepad button for an action. Moving is done with the stick, the controls screen and the map need a keyboard and mouse,
+/// and saving, loading and replays are left to the keyboard too.
+fn button(action: Action) -> Option<Button> {
+    match action {
+        Action::EnterVehicle => Some(Button::North),
+        Action::EnterAsPassenger => Some(Button::DPadUp),
+        Action::Fire => Some(Button::South),
+        Action::Handbrake => Some(Button::East),
+        Action::ShiftUp => Some(Button::RightTrigger),
+        Action::ShiftDown => Some(Button::LeftTrigger),
+        Action::WeaponWheel => Some(Button::West),
+        Action::ControlScheme => Some(Button::Select),
+        Action::ToggleHud => Some(Button::DPadDown),
+        Action::Controls
+        | Action::Map
+        | Action::MoveUp
+        | Action::MoveDown
+        | Action::MoveLeft
+        | Action::MoveRight
+        | Action::QuickSave
+        | Action::QuickLoad
+        | Action::NextSaveSlot
+        | Action::SaveReplay => None,
+    }
+}
@@ -0,0 +1,103 @@
+use std::rc::Rc;
+
+use macroquad::prelude::*;
+
+use crate::{
+    map::Map,
+    navigation::{Navigation, Route},
+    player::Player,
+    road::RoadGraph,
+};
+
+/// Closer than this to the destination and the player has arrived.
+pub const ARRIVAL_DISTANCE: f32 = 400.;
+/// Space between the arrows drawn on the road, and how far ahead of the player they go.
+const ARROW_SPACING: f32 = 300.;
+const ARROW_RANGE: f32 = 3000.;
+const ARROW_SIZE: f32 = 40.;
+pub const ROUTE_COLOR: Color = Color::new(1., 0., 1., 0.7);
+
+/// Driving directions from the player's vehicle to where they want to go.
+/// Plans with its own `Navigation`, so the routes it caches never change what the AI drivers do.
+pub struct Gps {
+    navigation: Navigation,
+    pub route: Option<Rc<Route>>,
+    destination: Option<Vec2>,
+    /// Tile the player was on when no route to `destination` was found.
+    /// Searching again from there would fail the same way.
+    unroutable_from: Option<(usize, usize)>,
+}
+
+impl Gps {
+    pub fn new(map: &Map) -> Self {
+        Gps {
+            navigation: Navigation::new(map),
+            route: None,
+            destination: None,
+            unroutable_from: None,
+        }
+    }
+
+    /// Plans a route when the destination changes, and a new one whenever the player drives off it.
+    /// There is no route while the player is on foot or has arrived.
+    /// A destination without a route is only tried again once the player reaches another tile.
+    pub fn update(&mut self, roads: &RoadGraph, map: &Map, player: &Player, destination: Option<Vec2>) {
+        let Some(vehicle) = player.driving() else {
+            self.clear();
+            return;
+        };
+        let Some(destination) = destination.filter(|d| d.distance(player.pos) > ARRIVAL_DISTANCE) else {
+            self.clear();
+            return;
+        };
+        let heading = Some(vehicle.borrow().rotation);
+        if self.destination != Some(destination) {
+            self.destination = Some(destination);
+            self.route = None;
+            self.unroutable_from = None;
+        }
+        match &self.route {
+            Some(route) => {
+                if let Some(route) = self.navigation.replan(roads, map, route, player.pos, heading) {
+                    self.route = Some(route);
+                }
+            }
+            None => {
+                let tile = map.to_tile_index_pos(player.pos);
+                if self.unroutable_from != Some(tile) {
+                    self.route = self.navigation.vehicle_route(roads, map, player.pos, heading, destination);
+                    if self.route.is_none() {
+                        self.unroutable_from = Some(tile);
+                    }
+                }
+            }
+        }
+    }
+
+    fn clear(&mut self) {
+        self.route = None;
+        self.destination = None;
+    }
+
+    /// Arrows on the road showing the way for the next stretch of the route, with the camera on the player.
+    pub fn draw(&self, player_pos: Vec2) {
+        let Some(route) = &self.route else {
+            return;
+        };
+        let offset = Vec2::new(screen_width(), screen_height()) / 2. - player_pos;
+        let mut ahead = ARROW_SPACING;
+        while ahead < ARROW_RANGE {
+            let pos = route.point_ahead(player_pos, ahead);
+            let direction = (route.point_ahead(player_pos, ahead + 1.) - pos).normalize_or_zero();
+            if direction == Vec2::ZERO {
+                // The end of the route
+                break;
+            }
+            let right = Vec2::new(-direction.y, direction.x);
+            let tip = pos + offset + direction * ARROW_SIZE;
+            let back = pos + offset - direction * ARROW_SIZE * 0.5;
+            draw_triangle(tip, back + right * ARROW_SIZE, back - right * ARROW_SIZE, ROUTE_COLOR);
+            ahead += ARROW_SPACING;
+        }
+    }
+}
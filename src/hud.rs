@@ -0,0 +1,191 @@
+use macroquad::prelude::*;
+
+use crate::{
+    game::Game,
+    player::{MAX_ARMOR, MAX_HEALTH},
+    vehicle::{Vehicle, REDLINE_RPM},
+    wanted::MAX_STARS,
+};
+
+/// The layout is made for a window this high and scaled to the actual one.
+const REFERENCE_HEIGHT: f32 = 720.;
+const MARGIN: f32 = 20.;
+/// Where the speedometer needle stops.
+const MAX_SPEED_KMH: f32 = 240.;
+/// The tachometer is marked red from here to the redline.
+const RED_ZONE_RPM: f32 = 6000.;
+/// Gauges sweep this many degrees clockwise, starting at `GAUGE_START` (0 is pointing right).
+const GAUGE_SWEEP: f32 = 270.;
+const GAUGE_START: f32 = 135.;
+const GAUGE_RADIUS: f32 = 70.;
+const BAR_WIDTH: f32 = 220.;
+const BAR_HEIGHT: f32 = 16.;
+const PANEL: Color = Color::new(0., 0., 0., 0.5);
+
+/// Everything drawn on top of the world: gauges, health, money, wanted level, weapon and mission.
+pub struct Hud {
+    pub visible: bool,
+}
+
+impl Default for Hud {
+    fn default() -> Self {
+        Hud { visible: true }
+    }
+}
+
+impl Hud {
+    pub fn toggle(&mut self) {
+        self.visible = !self.visible;
+    }
+
+    pub fn draw(&self, game: &Game) {
+        if !self.visible {
+            return;
+        }
+        let scale = screen_height() / REFERENCE_HEIGHT;
+        let player = &game.player;
+        let margin = MARGIN * scale;
+
+        // Top right: wanted level and money
+        let star_radius = 14. * scale;
+        let stars = game.world.wanted.stars();
+        let searching = game.world.wanted.is_searching();
+        for i in 0..MAX_STARS {
+            let x = screen_width() - margin - star_radius - (MAX_STARS - 1 - i) as f32 * star_radius * 2.2;
+            let color = match (i < stars, searching) {
+                (true, false) => GOLD,
+                (true, true) => GRAY,
+                (false, _) => Color::new(1., 1., 1., 0.2),
+            };
+            draw_star(Vec2::new(x, margin + star_radius), star_radius, color);
+        }
+        draw_text_right(
+            &format!("${}", player.money),
+            screen_width() - margin,
+            margin + star_radius * 2. + 36. * scale,
+            40. * scale,
+            Color::new(0.4, 0.9, 0.4, 1.),
+        );
+
+        // Top left: the current mission objective
+        if let Some(objective) = game.missions.current_objective() {
+            let time_left = game.missions.active.as_ref().and_then(|a| a.time_left);
+            let text = match time_left {
+                Some(t) => format!("{} ({:.0}s)", objective.text, t.max(0.)),
+                None => objective.text.clone(),
+            };
+            draw_text(&text, margin, margin + 30. * scale, 34. * scale, GOLD);
+        }
+
+        // Bottom left: health and armor
+        let bar_width = BAR_WIDTH * scale;
+        let bar_height = BAR_HEIGHT * scale;
+        let health_y = screen_height() - margin - bar_height;
+        draw_bar(margin, health_y, bar_width, bar_height, player.health / MAX_HEALTH, RED);
+        if player.armor > 0. {
+            let armor_y = health_y - bar_height - 6. * scale;
+            draw_bar(margin, armor_y, bar_width, bar_height, player.armor / MAX_ARMOR, SKYBLUE);
+        }
+
+        // Bottom right: weapon and ammo
+        if let Some(weapon) = player.weapon() {
+            let y = screen_height() - margin;
+            draw_text_right(&format!("{}", weapon.ammo), screen_width() - margin, y, 48. * scale, WHITE);
+            draw_text_right(
+                &format!("{:?}", weapon.kind),
+                screen_width() - margin,
+                y - 44. * scale,
+                28. * scale,
+                LIGHTGRAY,
+            );
+        }
+
+        // Bottom center: gauges while driving
+        if let Some(vehicle) = player.driving() {
+            draw_gauges(&vehicle.borrow(), scale);
+        }
+    }
+}
+
+/// Speedometer and tachometer side by side, with the gear between them.
+fn draw_gauges(vehicle: &Vehicle, scale: f32) {
+    let radius = GAUGE_RADIUS * scale;
+    let y = screen_height() - MARGIN * scale - radius;
+    let speed_center = Vec2::new(screen_width() / 2. - radius * 1.3, y);
+    let rpm_center = Vec2::new(screen_width() / 2. + radius * 1.3, y);
+
+    let kmh = vehicle.velocity * 3.6;
+    draw_gauge(speed_center, radius, kmh / MAX_SPEED_KMH, None);
+    draw_text_centered(&format!("{kmh:.0}"), speed_center + Vec2::new(0., radius * 0.5), 30. * scale, WHITE);
+    draw_text_centered("km/h", speed_center + Vec2::new(0., radius * 0.8), 18. * scale, LIGHTGRAY);
+
+    draw_gauge(rpm_center, radius, vehicle.rpm / REDLINE_RPM, Some(RED_ZONE_RPM / REDLINE_RPM));
+    draw_text_centered(
+        &format!("{:.1}", vehicle.rpm / 1000.),
+        rpm_center + Vec2::new(0., radius * 0.5),
+        30. * scale,
+        WHITE,
+    );
+    draw_text_centered("x1000 rpm", rpm_center + Vec2::new(0., radius * 0.8), 18. * scale, LIGHTGRAY);
+
+    let gear = if vehicle.reversed {
+        "R".to_string()
+    } else {
+        (vehicle.gearbox.current_gear_num() + 1).to_string()
+    };
+    let gear_center = Vec2::new(screen_width() / 2., y);
+    draw_circle(gear_center.x, gear_center.y, 26. * scale, PANEL);
+    draw_text_centered(&gear, gear_center + Vec2::new(0., 14. * scale), 44. * scale, WHITE);
+}
+
+/// A round dial with its needle at `fraction` of the sweep. Everything from `redline` on is marked red.
+fn draw_gauge(center: Vec2, radius: f32, fraction: f32, redline: Option<f32>) {
+    draw_circle(center.x, center.y, radius, PANEL);
+    let thickness = radius * 0.08;
+    draw_arc(center.x, center.y, 48, radius * 0.9, GAUGE_START, thickness, GAUGE_SWEEP, LIGHTGRAY);
+    if let Some(redline) = redline {
+        draw_arc(
+            center.x,
+            center.y,
+            16,
+            radius * 0.9,
+            GAUGE_START + GAUGE_SWEEP * redline,
+            thickness,
+            GAUGE_SWEEP * (1. - redline),
+            RED,
+        );
+    }
+    let angle = (GAUGE_START + GAUGE_SWEEP * fraction.clamp(0., 1.)).to_radians();
+    let tip = center + Vec2::from_angle(angle) * radius * 0.8;
+    draw_line(center.x, center.y, tip.x, tip.y, thickness, ORANGE);
+    draw_circle(center.x, center.y, thickness * 1.5, ORANGE);
+}
+
+fn draw_bar(x: f32, y: f32, width: f32, height: f32, fraction: f32, color: Color) {
+    draw_rectangle(x, y, width, height, PANEL);
+    draw_rectangle(x, y, width * fraction.clamp(0., 1.), height, color);
+    draw_rectangle_lines(x, y, width, height, 2., BLACK);
+}
+
+/// A five pointed star around `center`.
+fn draw_star(center: Vec2, radius: f32, color: Color) {
+    let point = |i: usize, r: f32| {
+        let angle = -core::f32::consts::FRAC_PI_2 + i as f32 * core::f32::consts::PI / 5.;
+        center + Vec2::from_angle(angle) * r
+    };
+    for i in 0..10 {
+        let r = if i % 2 == 0 { radius } else { radius * 0.45 };
+        let next = if i % 2 == 0 { radius * 0.45 } else { radius };
+        draw_triangle(center, point(i, r), point(i + 1, next), color);
+    }
+}
+
+fn draw_text_right(text: &str, right: f32, y: f32, size: f32, color: Color) {
+    let width = measure_text(text, None, size as u16, 1.).width;
+    draw_text(text, right - width, y, size, color);
+}
+
+fn draw_text_centered(text: &str, center: Vec2, size: f32, color: Color) {
+    let width = measure_text(text, None, size as u16, 1.).width;
+    draw_text(text, center.x - width / 2., center.y, size, color);
+}
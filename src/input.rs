@@ -0,0 +1,381 @@
+use std::{collections::HashMap, fs};
+
+use macroquad::prelude::*;
+
+/// Where the player's key bindings are kept.
+pub const BINDINGS_PATH: &str = "controls.cfg";
+
+/// Something the player can do, independent of the key or button it is bound to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Action {
+    MoveUp,
+    MoveDown,
+    MoveLeft,
+    MoveRight,
+    EnterVehicle,
+    EnterAsPassenger,
+    Fire,
+    Handbrake,
+    ShiftUp,
+    ShiftDown,
+    WeaponWheel,
+    ControlScheme,
+    ToggleHud,
+    Map,
+    Controls,
+    QuickSave,
+    QuickLoad,
+    NextSaveSlot,
+    SaveReplay,
+}
+
+impl Action {
+    pub const ALL: [Action; 19] = [
+        Action::MoveUp,
+        Action::MoveDown,
+        Action::MoveLeft,
+        Action::MoveRight,
+        Action::EnterVehicle,
+        Action::EnterAsPassenger,
+        Action::Fire,
+        Action::Handbrake,
+        Action::ShiftUp,
+        Action::ShiftDown,
+        Action::WeaponWheel,
+        Action::ControlScheme,
+        Action::ToggleHud,
+        Action::Map,
+        Action::Controls,
+        Action::QuickSave,
+        Action::QuickLoad,
+        Action::NextSaveSlot,
+        Action::SaveReplay,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Action::MoveUp => "MoveUp",
+            Action::MoveDown => "MoveDown",
+            Action::MoveLeft => "MoveLeft",
+            Action::MoveRight => "MoveRight",
+            Action::EnterVehicle => "EnterVehicle",
+            Action::EnterAsPassenger => "EnterAsPassenger",
+            Action::Fire => "Fire",
+            Action::Handbrake => "Handbrake",
+            Action::ShiftUp => "ShiftUp",
+            Action::ShiftDown => "ShiftDown",
+            Action::WeaponWheel => "WeaponWheel",
+            Action::ControlScheme => "ControlScheme",
+            Action::ToggleHud => "ToggleHud",
+            Action::Map => "Map",
+            Action::Controls => "Controls",
+            Action::QuickSave => "QuickSave",
+            Action::QuickLoad => "QuickLoad",
+            Action::NextSaveSlot => "NextSaveSlot",
+            Action::SaveReplay => "SaveReplay",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Action> {
+        Action::ALL.into_iter().find(|a| a.name() == name)
+    }
+}
+
+/// A key or mouse button an action can be bound to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Binding {
+    Key(KeyCode),
+    Mouse(MouseButton),
+}
+
+/// Keys that can be bound, by the name used in the bindings file.
+const KEY_NAMES: &[(&str, KeyCode)] = &[
+    ("A", KeyCode::A), ("B", KeyCode::B), ("C", KeyCode::C), ("D", KeyCode::D),
+    ("E", KeyCode::E), ("F", KeyCode::F), ("G", KeyCode::G), ("H", KeyCode::H),
+    ("I", KeyCode::I), ("J", KeyCode::J), ("K", KeyCode::K), ("L", KeyCode::L),
+    ("M", KeyCode::M), ("N", KeyCode::N), ("O", KeyCode::O), ("P", KeyCode::P),
+    ("Q", KeyCode::Q), ("R", KeyCode::R), ("S", KeyCode::S), ("T", KeyCode::T),
+    ("U", KeyCode::U), ("V", KeyCode::V), ("W", KeyCode::W), ("X", KeyCode::X),
+    ("Y", KeyCode::Y), ("Z", KeyCode::Z),
+    ("0", KeyCode::Key0), ("1", KeyCode::Key1), ("2", KeyCode::Key2), ("3", KeyCode::Key3),
+    ("4", KeyCode::Key4), ("5", KeyCode::Key5), ("6", KeyCode::Key6), ("7", KeyCode::Key7),
+    ("8", KeyCode::Key8), ("9", KeyCode::Key9),
+    ("Up", KeyCode::Up), ("Down", KeyCode::Down), ("Left", KeyCode::Left), ("Right", KeyCode::Right),
+    ("Space", KeyCode::Space), ("Enter", KeyCode::Enter), ("Tab", KeyCode::Tab),
+    ("Backspace", KeyCode::Backspace), ("Escape", KeyCode::Escape),
+    ("LeftShift", KeyCode::LeftShift), ("RightShift", KeyCode::RightShift),
+    ("LeftControl", KeyCode::LeftControl), ("RightControl", KeyCode::RightControl),
+    ("LeftAlt", KeyCode::LeftAlt), ("RightAlt", KeyCode::RightAlt),
+    ("F1", KeyCode::F1), ("F2", KeyCode::F2), ("F3", KeyCode::F3), ("F4", KeyCode::F4),
+    ("F5", KeyCode::F5), ("F6", KeyCode::F6), ("F7", KeyCode::F7), ("F8", KeyCode::F8),
+    ("F9", KeyCode::F9), ("F10", KeyCode::F10), ("F11", KeyCode::F11), ("F12", KeyCode::F12),
+    ("Kp0", KeyCode::Kp0), ("Kp1", KeyCode::Kp1), ("Kp2", KeyCode::Kp2), ("Kp3", KeyCode::Kp3),
+    ("Kp4", KeyCode::Kp4), ("Kp5", KeyCode::Kp5), ("Kp6", KeyCode::Kp6), ("Kp7", KeyCode::Kp7),
+    ("Kp8", KeyCode::Kp8), ("Kp9", KeyCode::Kp9),
+    ("Comma", KeyCode::Comma), ("Period", KeyCode::Period), ("Semicolon", KeyCode::Semicolon),
+    ("Minus", KeyCode::Minus), ("Equal", KeyCode::Equal), ("Slash", KeyCode::Slash),
+];
+
+const MOUSE_NAMES: &[(&str, MouseButton)] = &[
+    ("MouseLeft", MouseButton::Left),
+    ("MouseRight", MouseButton::Right),
+    ("MouseMiddle", MouseButton::Middle),
+];
+
+impl Binding {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Binding::Key(key) => KEY_NAMES.iter().find(|(_, k)| k == key).map(|(name, _)| *name),
+            Binding::Mouse(button) => MOUSE_NAMES.iter().find(|(_, b)| b == button).map(|(name, _)| *name),
+        }
+        .unwrap_or("?")
+    }
+
+    pub fn from_name(name: &str) -> Option<Binding> {
+        KEY_NAMES
+            .iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, key)| Binding::Key(*key))
+            .or_else(|| {
+                MOUSE_NAMES
+                    .iter()
+                    .find(|(n, _)| *n == name)
+                    .map(|(_, button)| Binding::Mouse(*button))
+            })
+    }
+
+    pub fn is_down(&self) -> bool {
+        match self {
+            Binding::Key(key) => is_key_down(*key),
+            Binding::Mouse(button) => is_mouse_button_down(*button),
+        }
+    }
+
+    pub fn is_pressed(&self) -> bool {
+        match self {
+            Binding::Key(key) => is_key_pressed(*key),
+            Binding::Mouse(button) => is_mouse_button_pressed(*button),
+        }
+    }
+
+    /// The key or mouse button pressed this frame, if it can be bound.
+    pub fn pressed() -> Option<Binding> {
+        if let Some((_, button)) = MOUSE_NAMES.iter().find(|(_, b)| is_mouse_button_pressed(*b)) {
+            return Some(Binding::Mouse(*button));
+        }
+        let key = get_last_key_pressed()?;
+        KEY_NAMES.iter().any(|(_, k)| *k == key).then_some(Binding::Key(key))
+    }
+}
+
+/// Which keys and buttons trigger which actions.
+#[derive(Debug, Clone)]
+pub struct Bindings {
+    bindings: HashMap<Action, Vec<Binding>>,
+    /// How far a gamepad stick has to move from the center before it does anything, from 0 to 1.
+    pub stick_deadzone: f32,
+    /// The same for the triggers.
+    pub trigger_deadzone: f32,
+}
+
+impl Default for Bindings {
+    fn default() -> Self {
+        use Binding::*;
+        let bindings = [
+            (Action::MoveUp, vec![Key(KeyCode::W), Key(KeyCode::Up)]),
+            (Action::MoveDown, vec![Key(KeyCode::S), Key(KeyCode::Down)]),
+            (Action::MoveLeft, vec![Key(KeyCode::A), Key(KeyCode::Left)]),
+            (Action::MoveRight, vec![Key(KeyCode::D), Key(KeyCode::Right)]),
+            (Action::EnterVehicle, vec![Key(KeyCode::F)]),
+            (Action::EnterAsPassenger, vec![Key(KeyCode::G)]),
+            (Action::Fire, vec![Mouse(MouseButton::Left)]),
+            (Action::Handbrake, vec![Key(KeyCode::Space)]),
+            (Action::ShiftUp, vec![Key(KeyCode::E)]),
+            (Action::ShiftDown, vec![Key(KeyCode::Q)]),
+            (Action::WeaponWheel, vec![Key(KeyCode::Tab)]),
+            (Action::ControlScheme, vec![Key(KeyCode::C)]),
+            (Action::ToggleHud, vec![Key(KeyCode::H)]),
+            (Action::Map, vec![Key(KeyCode::M)]),
+            (Action::Controls, vec![Key(KeyCode::F1)]),
+            (Action::QuickSave, vec![Key(KeyCode::F5)]),
+            (Action::QuickLoad, vec![Key(KeyCode::F9)]),
+            (Action::NextSaveSlot, vec![Key(KeyCode::F6)]),
+            (Action::SaveReplay, vec![Key(KeyCode::F12)]),
+        ];
+        Bindings {
+            bindings: bindings.into_iter().collect(),
+            stick_deadzone: 0.2,
+            trigger_deadzone: 0.1,
+        }
+    }
+}
+
+impl Bindings {
+    /// Reads bindings from a file with one `Action = Key, Key` line per action,
+    /// and `StickDeadzone = 0.2` and `TriggerDeadzone = 0.1` lines for the gamepad.
+    /// Anything missing from the file keeps its default.
+    pub fn load(path: &str) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("Failed to read {path}: {e}"))?;
+        let mut bindings = Bindings::default();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (action, keys) = line
+                .split_once('=')
+                .ok_or_else(|| format!("{path}:{}: expected `Action = Key, Key`", number + 1))?;
+            let deadzone = match action.trim() {
+                "StickDeadzone" => Some(&mut bindings.stick_deadzone),
+                "TriggerDeadzone" => Some(&mut bindings.trigger_deadzone),
+                _ => None,
+            };
+            if let Some(deadzone) = deadzone {
+                *deadzone = keys
+                    .trim()
+                    .parse::<f32>()
+                    .ok()
+                    .filter(|d| (0. ..1.).contains(d))
+                    .ok_or_else(|| format!("{path}:{}: deadzone must be between 0 and 1", number + 1))?;
+                continue;
+            }
+            let action = Action::from_name(action.trim())
+                .ok_or_else(|| format!("{path}:{}: unknown action {}", number + 1, action.trim()))?;
+            let keys = keys
+                .split(',')
+                .map(str::trim)
+                .filter(|key| !key.is_empty())
+                .map(|key| Binding::from_name(key).ok_or_else(|| format!("{path}:{}: unknown key {key}", number + 1)))
+                .collect::<Result<Vec<_>, _>>()?;
+            bindings.bindings.insert(action, keys);
+        }
+        Ok(bindings)
+    }
+
+    /// The bindings from `BINDINGS_PATH`, or the defaults if there are none yet.
+    pub fn load_or_default() -> Self {
+        match Bindings::load(BINDINGS_PATH) {
+            Ok(bindings) => bindings,
+            Err(e) => {
+                if std::path::Path::new(BINDINGS_PATH).exists() {
+                    println!("{e}, using default controls");
+                }
+                Bindings::default()
+            }
+        }
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        let mut text = String::from("# Action = Key, Key\n");
+        for action in Action::ALL {
+            let keys: Vec<&str> = self.get(action).iter().map(|b| b.name()).collect();
+            text.push_str(&format!("{} = {}\n", action.name(), keys.join(", ")));
+        }
+        text.push_str(&format!("StickDeadzone = {}\n", self.stick_deadzone));
+        text.push_str(&format!("TriggerDeadzone = {}\n", self.trigger_deadzone));
+        fs::write(path, text).map_err(|e| format!("Failed to write {path}: {e}"))
+    }
+
+    pub fn get(&self, action: Action) -> &[Binding] {
+        self.bindings.get(&action).map(Vec::as_slice).unwrap_or(&[])
+    }
+
+    pub fn set(&mut self, action: Action, bindings: Vec<Binding>) {
+        self.bindings.insert(action, bindings);
+    }
+
+    /// Makes `binding` the primary binding of `action`, replacing the old one.
+    /// An action that already had `binding` gets the replaced one instead, so no key does two things.
+    pub fn bind(&mut self, action: Action, binding: Binding) {
+        let mut keys = self.get(action).to_vec();
+        let replaced = keys.first().copied().filter(|k| *k != binding);
+        keys.retain(|k| *k != binding);
+        keys.insert(0, binding);
+        if let Some(replaced) = replaced {
+            keys.retain(|k| *k != replaced);
+        }
+        for (other, other_keys) in &mut self.bindings {
+            if *other == action {
+                continue;
+            }
+            if let Some(i) = other_keys.iter().position(|k| *k == binding) {
+                match replaced {
+                    Some(replaced) if !other_keys.contains(&replaced) => other_keys[i] = replaced,
+                    _ => {
+                        other_keys.remove(i);
+                    }
+                }
+            }
+        }
+        self.bindings.insert(action, keys);
+    }
+
+    pub fn is_down(&self, action: Action) -> bool {
+        self.get(action).iter().any(Binding::is_down)
+    }
+
+    pub fn is_pressed(&self, action: Action) -> bool {
+        self.get(action).iter().any(Binding::is_pressed)
+    }
+}
+
+/// In-game screen listing every action with its bindings, where they can be changed.
+#[derive(Default)]
+pub struct RebindScreen {
+    pub open: bool,
+    selected: usize,
+    /// Waiting for the key to bind to the selected action.
+    capturing: bool,
+}
+
+impl RebindScreen {
+    /// Handles the keyboard while the screen is open. Bindings are saved when it is closed.
+    pub fn update(&mut self, bindings: &mut Bindings) {
+        if self.capturing {
+            if is_key_pressed(KeyCode::Escape) {
+                self.capturing = false;
+            } else if let Some(binding) = Binding::pressed() {
+                bindings.bind(Action::ALL[self.selected], binding);
+                self.capturing = false;
+            }
+            return;
+        }
+        if is_key_pressed(KeyCode::Escape) {
+            self.open = false;
+            if let Err(e) = bindings.save(BINDINGS_PATH) {
+                println!("{e}");
+            }
+        } else if is_key_pressed(KeyCode::Up) {
+            self.selected = (self.selected + Action::ALL.len() - 1) % Action::ALL.len();
+        } else if is_key_pressed(KeyCode::Down) {
+            self.selected = (self.selected + 1) % Action::ALL.len();
+        } else if is_key_pressed(KeyCode::Enter) {
+            self.capturing = true;
+        } else if is_key_pressed(KeyCode::Backspace) {
+            bindings.set(Action::ALL[self.selected], Vec::new());
+        }
+    }
+
+    pub fn draw(&self, bindings: &Bindings) {
+        draw_rectangle(0., 0., screen_width(), screen_height(), Color::new(0., 0., 0., 0.8));
+        draw_text("Controls", 60., 70., 50., WHITE);
+        draw_text(
+            "Up/Down: select   Enter: bind key   Backspace: clear   Escape: save and close",
+            60.,
+            105.,
+            24.,
+            GRAY,
+        );
+        for (i, action) in Action::ALL.iter().enumerate() {
+            let y = 150. + i as f32 * 29.;
+            let color = if i == self.selected { YELLOW } else { WHITE };
+            draw_text(action.name(), 60., y, 28., color);
+            let keys = if self.capturing && i == self.selected {
+                "press a key, Escape to cancel".to_string()
+            } else {
+                bindings.get(*action).iter().map(|b| b.name()).collect::<Vec<_>>().join(", ")
+            };
+            draw_text(&keys, 400., y, 28., color);
+        }
+    }
+}
@@ -0,0 +1,182 @@
+use std::collections::HashMap;
+
+use macroquad::prelude::*;
+
+use crate::{
+    map::{Map, TILE_TEX_SIZE_SCALED},
+    rng::Rng,
+    Direction,
+};
+
+const GREEN_DURATION: f32 = 7.;
+const AMBER_DURATION: f32 = 2.;
+/// All vehicle lights are red while pedestrians cross, in any direction including diagonally.
+const WALK_DURATION: f32 = 5.;
+const CYCLE_DURATION: f32 = 2. * (GREEN_DURATION + AMBER_DURATION) + WALK_DURATION;
+
+/// Distance of a light from the edges of its crossing tile.
+const LIGHT_INSET: f32 = 40.;
+const LIGHT_RADIUS: f32 = 14.;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Signal {
+    Green,
+    Amber,
+    Red,
+}
+
+impl Signal {
+    pub fn color(&self) -> Color {
+        match self {
+            Signal::Green => GREEN,
+            Signal::Amber => ORANGE,
+            Signal::Red => RED,
+        }
+    }
+}
+
+/// Part of the signal cycle of an intersection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    NorthSouth(Signal),
+    EastWest(Signal),
+    Pedestrians,
+}
+
+/// Traffic lights of one crossing or T-junction tile.
+pub struct IntersectionController {
+    pub tile: (usize, usize),
+    pub pos: Vec2,
+    /// Directions of travel of the vehicles approaching the intersection.
+    pub approaches: Vec<Direction>,
+    /// Position in the signal cycle.
+    time: f32,
+}
+
+impl IntersectionController {
+    pub fn new(map: &Map, tile: (usize, usize), rng: &mut Rng) -> Self {
+        IntersectionController {
+            tile,
+            pos: map.tile_center(tile),
+            approaches: Direction::CARDINALS
+                .into_iter()
+                .filter(|d| map.road_connects(tile, *d))
+                .map(|d| d.opposite())
+                .collect(),
+            // Neighbouring intersections shouldn't all switch at once
+            time: rng.gen_range(0., CYCLE_DURATION),
+        }
+    }
+
+    pub fn phase(&self) -> Phase {
+        let mut time = self.time;
+        for axis in [Phase::NorthSouth, Phase::EastWest] {
+            if time < GREEN_DURATION {
+                return axis(Signal::Green);
+            }
+            time -= GREEN_DURATION;
+            if time < AMBER_DURATION {
+                return axis(Signal::Amber);
+            }
+            time -= AMBER_DURATION;
+        }
+        Phase::Pedestrians
+    }
+
+    /// The light shown to vehicles travelling in direction `heading`.
+    pub fn signal(&self, heading: Vec2) -> Signal {
+        let north_south = heading.y.abs() >= heading.x.abs();
+        match self.phase() {
+            Phase::NorthSouth(signal) if north_south => signal,
+            Phase::EastWest(signal) if !north_south => signal,
+            _ => Signal::Red,
+        }
+    }
+
+    pub fn update(&mut self, dt: f32) {
+        self.time = (self.time + dt) % CYCLE_DURATION;
+    }
+
+    fn draw(&self, offset: Vec2) {
+        let inset = TILE_TEX_SIZE_SCALED / 2. - LIGHT_INSET;
+        for travel in &self.approaches {
+            let travel = travel.as_vector();
+            // On the right hand side of the road, before the crossing
+            let right = Vec2::new(-travel.y, travel.x);
+            let pos = self.pos - travel * inset + right * inset + offset;
+            draw_circle(pos.x, pos.y, LIGHT_RADIUS + 4., BLACK);
+            draw_circle(pos.x, pos.y, LIGHT_RADIUS, self.signal(travel).color());
+        }
+    }
+}
+
+/// Traffic lights of every intersection on the map.
+#[derive(Default)]
+pub struct Intersections {
+    pub controllers: Vec<IntersectionController>,
+    by_tile: HashMap<(usize, usize), usize>,
+    /// Tile the watched vehicle was on last time `ran_red_light` was asked.
+    watched_tile: Option<(usize, usize)>,
+}
+
+impl Intersections {
+    /// Puts lights on every tile where more than two roads meet.
+    pub fn from_map(map: &Map, rng: &mut Rng) -> Self {
+        let mut intersections = Intersections::default();
+        for (tile, _) in map.indexed_tiles() {
+            let connections = Direction::CARDINALS
+                .iter()
+                .filter(|d| map.road_connects(tile, **d))
+                .count();
+            if connections > 2 {
+                intersections.by_tile.insert(tile, intersections.controllers.len());
+                intersections.controllers.push(IntersectionController::new(map, tile, rng));
+            }
+        }
+        intersections
+    }
+
+    pub fn controller(&self, tile: (usize, usize)) -> Option<&IntersectionController> {
+        self.by_tile.get(&tile).map(|i| &self.controllers[*i])
+    }
+
+    /// The light shown at `tile` to vehicles travelling in direction `heading`.
+    /// `None` if there are no lights at that tile.
+    pub fn signal(&self, tile: (usize, usize), heading: Vec2) -> Option<Signal> {
+        self.controller(tile).map(|c| c.signal(heading))
+    }
+
+    /// Whether pedestrians may walk onto `tile`. Always true where there are no lights.
+    pub fn may_cross(&self, tile: (usize, usize)) -> bool {
+        self.controller(tile)
+            .is_none_or(|c| c.phase() == Phase::Pedestrians)
+    }
+
+    /// Whether a vehicle at `pos` driving in direction `heading` just drove onto a crossing showing red.
+    /// Only keeps track of a single vehicle, e.g. the one the player is driving.
+    pub fn ran_red_light(&mut self, map: &Map, pos: Vec2, heading: Vec2) -> bool {
+        let tile = map.to_tile_index_pos(pos);
+        let entered = self.watched_tile.replace(tile).is_some_and(|last| last != tile);
+        entered && self.signal(tile, heading) == Some(Signal::Red)
+    }
+
+    /// Stops watching the vehicle passed to `ran_red_light`, e.g. when the player gets out.
+    pub fn stop_watching(&mut self) {
+        self.watched_tile = None;
+    }
+
+    pub fn update(&mut self, dt: f32) {
+        for controller in &mut self.controllers {
+            controller.update(dt);
+        }
+    }
+
+    pub fn draw(&self, camera_pos: Vec2) {
+        let offset = Vec2::new(screen_width(), screen_height()) / 2. - camera_pos;
+        for controller in &self.controllers {
+            if controller.pos.distance(camera_pos) < screen_width() {
+                controller.draw(offset);
+            }
+        }
+    }
+}
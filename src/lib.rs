@@ -1,20 +1,56 @@
+pub mod animation;
+pub mod assets;
 pub mod collide;
 pub mod controller;
 pub mod draw;
+pub mod event;
+pub mod game;
+pub mod gps;
+pub mod hud;
+#[cfg(feature = "gamepad")]
+pub mod gamepad;
+pub mod input;
+pub mod intersection;
 pub mod map;
+pub mod minimap;
+pub mod mission;
+pub mod navigation;
+pub mod pedestrian;
 pub mod weapons;
 pub mod player;
+pub mod police;
+pub mod replay;
+pub mod rng;
+pub mod road;
+pub mod save;
+pub mod script;
+pub mod traffic;
+pub mod trigger;
 pub mod util;
 pub mod vehicle;
+pub mod wanted;
 
 use std::{cell::RefCell, rc::Rc};
 
 use collide::Collide;
+use draw::Draw;
 use macroquad::prelude::*;
+use intersection::Intersections;
 use map::Map;
-use vehicle::Vehicle;
+use minimap::Blip;
+use navigation::Navigation;
+use pedestrian::{Crowd, CrowdEvents};
+use player::{EntryEvent, Player};
+use police::{Police, PoliceUnit, PursuitTarget, Roadblock, Shot};
+use rng::Rng;
+use road::{EdgeId, RoadGraph};
+use event::{Entity, EventBus, GameEvent};
+use trigger::Triggers;
+use traffic::{Traffic, TrafficDriver};
+use vehicle::{catalogue::VehicleCatalogue, Door, Occupant, Vehicle};
+use wanted::{Crime, WantedLevel};
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Direction {
     North,
     NorthEast,
@@ -27,7 +63,14 @@ pub enum Direction {
 }
 
 impl Direction {
-    fn as_vector(&self) -> Vec2 {
+    pub const CARDINALS: [Direction; 4] = [
+        Direction::North,
+        Direction::East,
+        Direction::South,
+        Direction::West,
+    ];
+
+    pub fn as_vector(&self) -> Vec2 {
         match self {
             Direction::North => (0., -1.).into(),
             Direction::NorthEast => (45.0_f32.cos(), -45.0_f32.sin()).into(),
@@ -40,21 +83,49 @@ impl Direction {
         }
     }
     
-    fn has_north(&self) -> bool {
+    pub fn has_north(&self) -> bool {
         matches!(self, Direction::North | Direction::NorthWest | Direction::NorthEast)
     }
     
-    fn has_east(&self) -> bool {
+    pub fn has_east(&self) -> bool {
         matches!(self, Direction::East | Direction::SouthEast | Direction::NorthEast)
     }
     
-    fn has_south(&self) -> bool {
+    pub fn has_south(&self) -> bool {
         matches!(self, Direction::South | Direction::SouthEast | Direction::SouthWest)
     }
     
-    fn has_west(&self) -> bool {
+    pub fn has_west(&self) -> bool {
         matches!(self, Direction::West | Direction::NorthWest | Direction::SouthWest)
     }
+
+    /// The one of the eight directions closest to `vector`.
+    pub fn from_vector(vector: Vec2) -> Direction {
+        let octant = (vector.y.atan2(vector.x) / core::f32::consts::FRAC_PI_4).round() as i32;
+        match octant.rem_euclid(8) {
+            0 => Direction::East,
+            1 => Direction::SouthEast,
+            2 => Direction::South,
+            3 => Direction::SouthWest,
+            4 => Direction::West,
+            5 => Direction::NorthWest,
+            6 => Direction::North,
+            _ => Direction::NorthEast,
+        }
+    }
+
+    pub fn opposite(&self) -> Direction {
+        match self {
+            Direction::North => Direction::South,
+            Direction::NorthEast => Direction::SouthWest,
+            Direction::East => Direction::West,
+            Direction::SouthEast => Direction::NorthWest,
+            Direction::South => Direction::North,
+            Direction::SouthWest => Direction::NorthEast,
+            Direction::West => Direction::East,
+            Direction::NorthWest => Direction::SouthEast,
+        }
+    }
 }
 
 impl IntoIterator for Direction {
@@ -88,28 +159,410 @@ impl Iterator for DirectionIter {
 }
 
 pub trait Update {
-    fn update(&mut self);
+    fn update(&mut self, dt: f32);
 }
 
 pub struct World {
     pub vehicles: Vec<Rc<RefCell<Vehicle>>>,
     pub collideables: Vec<Rc<RefCell<dyn Collide>>>,
     pub map: Map,
+    pub roads: RoadGraph,
+    pub intersections: Intersections,
+    pub navigation: Navigation,
+    pub traffic: Traffic,
+    pub crowd: Crowd,
+    pub wanted: WantedLevel,
+    pub police: Police,
+    pub triggers: Triggers,
+    pub events: EventBus,
+    /// Markers placed on the maps by scripts, like shops.
+    pub blips: Vec<Blip>,
+    /// Every kind of vehicle that can be spawned.
+    pub vehicle_catalogue: VehicleCatalogue,
+    /// What weapons handed out during the game look like.
+    pub weapon_sprite: Option<Texture2D>,
+    /// Source of everything random in the world, so the same seed and inputs always give the same game.
+    pub rng: Rng,
 }
 
 impl World {
-    pub fn new(map: Map) -> Self {
+    pub fn new(map: Map, seed: u64) -> Self {
+        let mut rng = Rng::new(seed);
         World {
             vehicles: Vec::new(),
             collideables: Vec::new(),
+            roads: RoadGraph::from_map(&map),
+            intersections: Intersections::from_map(&map, &mut rng),
+            navigation: Navigation::new(&map),
+            traffic: Traffic::new(),
+            crowd: Crowd::new(),
+            wanted: WantedLevel::default(),
+            police: Police::new(),
+            triggers: Triggers::default(),
+            events: EventBus::default(),
+            blips: Vec::new(),
+            vehicle_catalogue: VehicleCatalogue::default(),
+            weapon_sprite: None,
+            rng,
             map,
         }
     }
 
-    pub fn add_vehicle(&mut self, vehicle: Vehicle) {
+    pub fn add_vehicle(&mut self, vehicle: Vehicle) -> Rc<RefCell<Vehicle>> {
         let vehicle_rc = Rc::new(RefCell::new(vehicle));
-        self.collideables.push(vehicle_rc.clone());
-        self.vehicles.push(vehicle_rc);
+        self.insert_vehicle(vehicle_rc.clone());
+        vehicle_rc
+    }
+
+    /// Adds a vehicle that is already shared, like one a script spawned and kept a handle to.
+    pub fn insert_vehicle(&mut self, vehicle: Rc<RefCell<Vehicle>>) {
+        self.collideables.push(vehicle.clone());
+        self.vehicles.push(vehicle);
+    }
+
+    /// Adds a new vehicle of the catalogue model called `model` at `pos`.
+    pub fn spawn_vehicle(&mut self, model: &str, pos: Vec2, rotation: Vec2) -> Result<Rc<RefCell<Vehicle>>, String> {
+        let mut vehicle = self.vehicle_catalogue.spawn(model)?;
+        vehicle.set_position(pos);
+        vehicle.rotation = rotation.normalize_or(Vec2::NEG_Y);
+        Ok(self.add_vehicle(vehicle))
+    }
+
+    /// Puts `vehicle` onto the lane of `edge`, `distance` units from its start, facing along the lane.
+    pub fn spawn_vehicle_on_lane(&mut self, mut vehicle: Vehicle, edge: EdgeId, distance: f32) -> Rc<RefCell<Vehicle>> {
+        let (pos, direction) = self.roads.edge(edge).point_at(distance);
+        vehicle.set_position(pos);
+        vehicle.rotation = direction;
+        self.add_vehicle(vehicle)
+    }
+
+    pub fn remove_vehicle(&mut self, vehicle: &Rc<RefCell<Vehicle>>) {
+        self.vehicles.retain(|v| !Rc::ptr_eq(v, vehicle));
+        self.collideables
+            .retain(|c| !std::ptr::addr_eq(Rc::as_ptr(c), Rc::as_ptr(vehicle)));
+    }
+
+    /// Despawns traffic that got left behind, spawns new traffic around the player
+    /// and lets every AI driver pick its inputs for this frame.
+    /// The driver of `boarding` waits for the player to get in as a passenger.
+    pub fn update_traffic(&mut self, player_pos: Vec2, player_on_foot: bool, boarding: Option<&Rc<RefCell<Vehicle>>>) {
+        for driver in self.traffic.take_far_away(player_pos) {
+            self.remove_vehicle(&driver.vehicle);
+        }
+        while self.traffic.drivers.len() < traffic::MAX_TRAFFIC_VEHICLES {
+            let Some(vehicle) = self
+                .vehicle_catalogue
+                .spawn_random(&self.traffic.vehicle_models, &mut self.rng)
+            else {
+                break;
+            };
+            let Some((edge, distance)) =
+                traffic::find_spawn_point(&self.roads, &self.map, player_pos, &self.vehicles, &mut self.rng)
+            else {
+                break;
+            };
+            let vehicle = self.spawn_vehicle_on_lane(vehicle, edge, distance);
+            self.traffic
+                .drivers
+                .push(TrafficDriver::new(vehicle, &self.roads, edge, distance, &mut self.rng));
+        }
+        let mut pedestrians: Vec<Vec2> = self.crowd.obstacles().collect();
+        if player_on_foot {
+            pedestrians.push(player_pos);
+        }
+        self.traffic
+            .drive(&self.roads, &self.intersections, &self.vehicles, &pedestrians, &mut self.rng);
+        if let Some(vehicle) = boarding.filter(|v| v.borrow().driver == Some(Occupant::Npc)) {
+            traffic::set_speed(&mut vehicle.borrow_mut(), 0.);
+        }
+    }
+
+    /// Keeps the streets around the player populated and moves every pedestrian.
+    /// `player_on_foot` is the player's position unless they are sitting in a vehicle.
+    pub fn update_crowd(&mut self, player_pos: Vec2, player_on_foot: Option<Vec2>, dt: f32) -> CrowdEvents {
+        self.crowd.despawn_far_away(player_pos);
+        self.crowd.spawn(&self.navigation, &self.map, player_pos, &mut self.rng);
+        let events = self.crowd.update(
+            &mut self.navigation,
+            &self.map,
+            &self.intersections,
+            &self.vehicles,
+            player_on_foot,
+            &mut self.rng,
+            dt,
+        );
+        for knockdown in &events.knockdowns {
+            self.publish_pedestrian_damage(knockdown.pedestrian, knockdown.damage);
+        }
+        events
+    }
+
+    /// Hits whoever is first in the line of a shot, of the crowd and the officers on foot.
+    /// Nobody further away than `cover`, where something else stopped the round, gets hit.
+    pub fn shoot_pedestrians(&mut self, from: Vec2, direction: Vec2, damage: f32, cover: Option<f32>) {
+        let in_front = |along: f32| cover.is_none_or(|cover| along < cover);
+        let crowd = pedestrian::first_in_line(self.crowd.pedestrians.iter_mut(), from, direction)
+            .filter(|(_, along)| in_front(*along));
+        let officers = self.police.officers.iter_mut().map(|officer| &mut officer.body);
+        let officer = pedestrian::first_in_line(officers, from, direction).filter(|(_, along)| in_front(*along));
+        match (crowd, officer) {
+            (Some((pedestrian, along)), officer) if officer.as_ref().is_none_or(|(_, o)| along < *o) => {
+                pedestrian.shot(damage, from);
+                let id = pedestrian.id;
+                self.publish_pedestrian_damage(id, damage);
+            }
+            (_, Some((officer, _))) => officer.shot(damage, from),
+            _ => {}
+        }
+    }
+
+    /// Publishes that the pedestrian `id` took `amount` of damage, and died if it did.
+    pub fn publish_pedestrian_damage(&mut self, id: u32, amount: f32) {
+        let entity = Entity::Pedestrian(id);
+        self.events.publish(GameEvent::EntityDamaged {
+            entity: entity.clone(),
+            amount,
+        });
+        if self.crowd.pedestrians.iter().any(|p| p.id == id && p.health <= 0.) {
+            self.events.publish(GameEvent::EntityDestroyed { entity });
+        }
+    }
+
+    /// Publishes what came into, stayed in or left the trigger zones since the last tick.
+    pub fn update_triggers(&mut self, player_pos: Vec2) {
+        for event in self
+            .triggers
+            .update(player_pos, &self.crowd.pedestrians, &self.vehicles)
+        {
+            self.events.publish(GameEvent::Zone(event));
+        }
+    }
+
+    /// The vehicle with the door closest to `pos` within `range`.
+    pub fn nearest_door(&self, pos: Vec2, range: f32) -> Option<(Rc<RefCell<Vehicle>>, Door)> {
+        self.vehicles
+            .iter()
+            .map(|v| (v, v.borrow().nearest_door(pos)))
+            .filter(|(_, (_, distance))| *distance < range)
+            .min_by(|a, b| a.1 .1.total_cmp(&b.1 .1))
+            .map(|(v, (door, _))| (v.clone(), door))
+    }
+
+    /// Advances the player getting into a vehicle and applies what that does to the world.
+    pub fn update_vehicle_entry(&mut self, player: &mut Player, dt: f32) -> Option<EntryEvent> {
+        let event = player.update_entry(dt)?;
+        if let EntryEvent::DriverPulledOut(vehicle) = &event {
+            self.eject_driver(vehicle, player.pos);
+        }
+        Some(event)
+    }
+
+    /// Takes the AI driver and any NPC passengers out of `vehicle` and puts them on the street next to the door,
+    /// from where they either run away or go for whoever pulled them out at `attacker_pos`.
+    pub fn eject_driver(&mut self, vehicle: &Rc<RefCell<Vehicle>>, attacker_pos: Vec2) {
+        let had_driver = self.traffic.remove_driver(vehicle).is_some()
+            || self.police.remove_unit(vehicle).is_some();
+        if !had_driver {
+            return;
+        }
+        let mut vehicle = vehicle.borrow_mut();
+        vehicle.driver = None;
+        vehicle.throttle = 0.;
+        vehicle.steer_neutral();
+        let door_pos = vehicle.door_position(vehicle.nearest_door(attacker_pos).0);
+        self.crowd.add_ejected_driver(door_pos, attacker_pos, &mut self.rng);
+        for _ in 0..vehicle.take_npc_passengers() {
+            let far_door = vehicle.door_position(vehicle.nearest_door(attacker_pos).0.opposite());
+            self.crowd.add_ejected_driver(far_door, attacker_pos, &mut self.rng);
+        }
+    }
+
+    /// Switches the traffic lights and reports the player's vehicle if it drove through a red light.
+    /// Returns the new number of stars if it changed.
+    pub fn update_intersections(&mut self, player_vehicle: Option<&Rc<RefCell<Vehicle>>>, dt: f32) -> Option<u8> {
+        self.intersections.update(dt);
+        let Some(vehicle) = player_vehicle else {
+            self.intersections.stop_watching();
+            return None;
+        };
+        let (pos, heading) = {
+            let vehicle = vehicle.borrow();
+            (vehicle.position(), vehicle.velocity_vector())
+        };
+        if heading == Vec2::ZERO || !self.intersections.ran_red_light(&self.map, pos, heading) {
+            return None;
+        }
+        self.report_crime(Crime::RanRedLight, pos)
+    }
+
+    /// Registers a crime the player committed at `pos`. Returns the new number of stars if it changed.
+    pub fn report_crime(&mut self, crime: Crime, pos: Vec2) -> Option<u8> {
+        let seen = self.police.can_see(pos);
+        self.events.publish(GameEvent::CrimeCommitted { crime, pos });
+        let stars = self.wanted.commit(crime, seen)?;
+        self.events.publish(GameEvent::WantedLevelChanged { stars });
+        Some(stars)
+    }
+
+    /// Puts the wanted level at `stars`, whatever the player did.
+    pub fn set_wanted_stars(&mut self, stars: u8) {
+        let before = self.wanted.stars();
+        self.wanted.set_stars(stars);
+        if self.wanted.stars() != before {
+            self.events.publish(GameEvent::WantedLevelChanged {
+                stars: self.wanted.stars(),
+            });
+        }
+    }
+
+    /// Pushes apart vehicles that ran into each other.
+    pub fn resolve_vehicle_collisions(&mut self) {
+        for (i, a) in self.vehicles.iter().enumerate() {
+            for b in &self.vehicles[i + 1..] {
+                if let Some(closing_speed) = vehicle::collide(&mut a.borrow_mut(), &mut b.borrow_mut()) {
+                    for vehicle in [a, b] {
+                        self.events.publish(GameEvent::EntityDamaged {
+                            entity: Entity::Vehicle(vehicle.clone()),
+                            amount: closing_speed * vehicle::COLLISION_DAMAGE,
+                        });
+                    }
+                }
+            }
+        }
+    }
+
+    /// Updates the wanted level, sends out or calls back police units to match it and drives them.
+    /// Depending on the tactics for the wanted level this also sets up roadblocks ahead of `player_vehicle`
+    /// and has officers get out and shoot at the player.
+    pub fn update_police(
+        &mut self,
+        player_pos: Vec2,
+        player_vehicle: Option<&Rc<RefCell<Vehicle>>>,
+        dt: f32,
+    ) -> PoliceUpdate {
+        let stars_changed = self.wanted.update(self.police.can_see(player_pos), dt);
+        if let Some(stars) = stars_changed {
+            self.events.publish(GameEvent::WantedLevelChanged { stars });
+        }
+        let tactics = self.wanted.tactics();
+        for unit in self.police.take_dismissed(player_pos, tactics.units) {
+            self.remove_vehicle(&unit.vehicle);
+        }
+        while self.police.units.len() < tactics.units {
+            let Some(vehicle) = self
+                .vehicle_catalogue
+                .spawn_random(&self.police.vehicle_models, &mut self.rng)
+            else {
+                break;
+            };
+            let Some((edge, distance)) =
+                traffic::find_spawn_point(&self.roads, &self.map, player_pos, &self.vehicles, &mut self.rng)
+            else {
+                break;
+            };
+            let vehicle = self.spawn_vehicle_on_lane(vehicle, edge, distance);
+            self.police
+                .units
+                .push(PoliceUnit::new(vehicle, &self.roads, edge, distance, &mut self.rng));
+        }
+        self.update_roadblock(player_pos, player_vehicle, tactics.roadblocks, dt);
+
+        let pedestrians: Vec<Vec2> = self.crowd.obstacles().collect();
+        let target = tactics.pursue.then(|| PursuitTarget {
+            pos: player_pos,
+            vehicle: player_vehicle.cloned(),
+        });
+        let events = self.police.update(
+            &self.roads,
+            &self.map,
+            &mut self.navigation,
+            &self.intersections,
+            &self.vehicles,
+            &pedestrians,
+            target,
+            tactics,
+            &mut self.rng,
+            dt,
+        );
+
+        let mut damage_to_player = 0.;
+        for shot in &events.shots {
+            self.crowd.alert_gunshot(shot.from);
+            self.events.publish(GameEvent::ShotFired {
+                from: shot.from,
+                direction: (player_pos - shot.from).normalize_or_zero(),
+                by_player: false,
+            });
+            if !shot.hit {
+                continue;
+            }
+            match player_vehicle {
+                Some(vehicle) => {
+                    vehicle.borrow_mut().damage += police::OFFICER_SHOT_DAMAGE;
+                    self.events.publish(GameEvent::EntityDamaged {
+                        entity: Entity::Vehicle(vehicle.clone()),
+                        amount: police::OFFICER_SHOT_DAMAGE,
+                    });
+                }
+                None => damage_to_player += police::OFFICER_SHOT_DAMAGE,
+            }
+        }
+        PoliceUpdate {
+            stars_changed,
+            damage_to_player,
+            shots: events.shots,
+        }
+    }
+
+    /// Puts up a roadblock ahead of the player's vehicle and takes it down again once it is passed.
+    fn update_roadblock(
+        &mut self,
+        player_pos: Vec2,
+        player_vehicle: Option<&Rc<RefCell<Vehicle>>>,
+        allowed: bool,
+        dt: f32,
+    ) {
+        for vehicle in self.police.take_cleared_roadblock(player_pos, allowed) {
+            // Unless someone drove off with it
+            if vehicle.borrow().driver.is_none() {
+                self.remove_vehicle(&vehicle);
+            }
+        }
+        let Some(player_vehicle) = player_vehicle.filter(|_| allowed) else {
+            return;
+        };
+        // Only roll for a model when one is needed, so the random numbers don't depend on how often this runs
+        if !self.police.roadblock_ready(dt) {
+            return;
+        }
+        let Some(model) = self.rng.choose(&self.police.vehicle_models).cloned() else {
+            return;
+        };
+        let heading = player_vehicle.borrow().rotation;
+        let Some((pos, direction)) = police::roadblock_site(&self.roads, &self.map, player_pos, heading) else {
+            return;
+        };
+        // Two cars parked across the road, one per lane
+        let across = Vec2::new(-direction.y, direction.x);
+        let vehicles = [-1., 1.]
+            .into_iter()
+            .filter_map(|side| {
+                let vehicle = self
+                    .spawn_vehicle(&model, pos + across * side * road::LANE_OFFSET, across)
+                    .map_err(|e| println!("{e}"))
+                    .ok()?;
+                vehicle.borrow_mut().siren = true;
+                Some(vehicle)
+            })
+            .collect();
+        self.police.roadblock = Some(Roadblock { pos, vehicles });
     }
 }
 
+/// What happened to the player because of the police during one update.
+pub struct PoliceUpdate {
+    /// The new number of stars if it changed.
+    pub stars_changed: Option<u8>,
+    pub damage_to_player: f32,
+    pub shots: Vec<Shot>,
+}
@@ -1,8 +1,8 @@
 use autotheft2d::{
-    collide::{LineSegment, Object2D}, controller::ControllerDirectionState, draw::{draw_vector, Draw}, map::Map, player::Player, util::{mouse_direction, SpiralIterator}, vehicle::Vehicle, weapons::{Projectile, Weapon}, Update, World
+    animation::{Animation, AnimationSet, SpriteSheet}, assets::{Assets, MANIFEST}, collide::{LineSegment, Object2D}, controller::ControllerDirectionState, draw::{draw_vector, Draw}, event::{Entity, GameEvent}, game::{Game, Sprites, TickInput, TICK}, gps::{self, Gps}, hud::Hud, input::{Action, Bindings, RebindScreen}, map::Map, minimap::{self, CityMap}, mission::Missions, replay::Recorder, script::Scripts, save::{self, SaveGame}, util::mouse_direction, vehicle::catalogue::VehicleCatalogue, weapons::Projectile
 };
+use std::rc::Rc;
 use macroquad::prelude::*;
-use std::{cell::RefCell, rc::Rc};
 const WINDOW_HEIGHT: i32 = 720;
 const WINDOW_WIDTH: i32 = 1280;
 
@@ -16,13 +16,55 @@ fn window_conf() -> Conf {
     }
 }
 
-macro_rules! include_texture {
-    ($path:expr) => {
-        Texture2D::from_file_with_format(include_bytes!($path), None)
-    };
+/// Picks up a data file changed while the game runs. Vehicles, missions and animations are used right away,
+/// scripts are read again when loading a save. Returns whether the running game changed.
+fn reload_data(
+    name: &str,
+    assets: &mut Assets,
+    sprites: &mut Sprites,
+    missions: &mut Missions,
+    game: &mut Game,
+) -> Result<bool, String> {
+    match name {
+        "vehicles" => {
+            let dir = assets.data_path(name)?.to_string();
+            sprites.vehicles = VehicleCatalogue::load(&dir, |path| assets.load_texture(path))?;
+            game.world.vehicle_catalogue = sprites.vehicles.clone();
+        }
+        "animations" => {
+            let old = std::mem::replace(
+                &mut sprites.player.animations,
+                Rc::new(AnimationSet::load(assets.data_path(name)?)?),
+            );
+            game.player.animation = Animation::new(sprites.player.animations.clone());
+            // Pedestrians and officers already around keep the clips they were made with, new ones get these
+            let world = &mut game.world;
+            for sheet in world.crowd.sprites.iter_mut().chain(&mut world.police.officer_sprites) {
+                if Rc::ptr_eq(&sheet.animations, &old) {
+                    sheet.animations = sprites.player.animations.clone();
+                }
+            }
+        }
+        "missions" => {
+            *missions = Missions::load(assets.data_path(name)?)?;
+            // The active mission refers to its definition by index
+            if game.missions.active.is_some() {
+                println!("reloaded {name}, the game gets them when loading a save");
+                return Ok(false);
+            }
+            game.missions.defs = missions.defs.clone();
+        }
+        _ => {
+            println!("{name} changed, it is read again when loading a save");
+            return Ok(false);
+        }
+    }
+    println!("reloaded {name}");
+    Ok(true)
 }
 
 // Example usage
+#[allow(dead_code)]
 fn test_collision() {
     // Example with a custom Object2D
     let custom_object = Object2D {
@@ -49,96 +91,263 @@ fn test_collision() {
     }
 }
 
+/// Frames taking longer than this slow the game down instead of simulating lots of ticks at once.
+const MAX_FRAME_TIME: f32 = 0.25;
+
+/// The seed passed with `--seed`, or a new one every run.
+fn seed_from_args() -> u64 {
+    let args: Vec<String> = std::env::args().collect();
+    args.iter()
+        .position(|arg| arg == "--seed")
+        .and_then(|i| args.get(i + 1))
+        .and_then(|seed| seed.parse().ok())
+        .unwrap_or_else(|| {
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|t| t.as_nanos() as u64)
+                .unwrap_or_default()
+        })
+}
+
+/// Prints what happens in the game to the console.
+fn log_events(game: &mut Game) {
+    game.world.events.subscribe(|event| match event {
+        GameEvent::VehicleEntered { .. } => println!("entered vehicle"),
+        GameEvent::VehicleExited { .. } => println!("left vehicle"),
+        GameEvent::WantedLevelChanged { stars } => println!("wanted level: {stars}"),
+        GameEvent::EntityDestroyed { entity: Entity::Player } => println!("wasted"),
+        GameEvent::MissionStarted { title, .. } => println!("mission started: {title}"),
+        GameEvent::ObjectiveStarted { text } => println!("{text}"),
+        GameEvent::MissionCompleted { id, reward } => println!("mission {id} passed, ${reward}"),
+        GameEvent::MissionFailed { reason, .. } => println!("mission failed: {reason}"),
+        _ => {}
+    });
+}
+
+/// Collects the sounds to play for what happens in the game, played once the frame's ticks are done.
+#[cfg(feature = "audio")]
+fn queue_sounds(game: &mut Game, queue: &Rc<std::cell::RefCell<Vec<&'static str>>>) {
+    let queue = queue.clone();
+    game.world.events.subscribe(move |event| {
+        if let GameEvent::ShotFired { .. } = event {
+            queue.borrow_mut().push("pistol");
+        }
+    });
+}
+
 #[macroquad::main(window_conf)]
 async fn main() {
-    let player_sprite = include_texture!("../assets/player.png");
-    let tile_map = include_texture!("../assets/map_tiles.png");
-    let vehicle_sprite = include_texture!("../assets/Car-0001.png");
-    let mut player = Player::new(player_sprite);
-    
-    let map = Map::new(tile_map, 1024);
-    let mut world = World::new(map);
-    let vehicle = Vehicle::new(vehicle_sprite);
-    world.add_vehicle(vehicle);
-    let mut controller = ControllerDirectionState::default();
-
-    let weapon_sprite = include_texture!("../assets/pistol.png");
-    let mut weapon = Weapon::new(
-        weapon_sprite,
-        2.,
-    );
+    let mut assets = Assets::load(MANIFEST).await.unwrap_or_else(|e| panic!("{e}"));
+    let texture = |name: &str| {
+        let handle = assets.texture_handle(name).unwrap_or_else(|e| panic!("{e}"));
+        assets.texture(handle).clone()
+    };
+    let (tile_map, player, weapon) = (texture("tile_map"), texture("player"), texture("pistol"));
+    let vehicle_dir = assets.data_path("vehicles").unwrap_or_else(|e| panic!("{e}")).to_string();
+    let mission_dir = assets.data_path("missions").unwrap_or_else(|e| panic!("{e}")).to_string();
+    let script_dir = assets.data_path("scripts").unwrap_or_else(|e| panic!("{e}")).to_string();
+    let animations = assets
+        .data_path("animations")
+        .and_then(AnimationSet::load)
+        .unwrap_or_else(|e| panic!("{e}"));
+    let vehicles =
+        VehicleCatalogue::load(&vehicle_dir, |path| assets.load_texture(path)).unwrap_or_else(|e| panic!("{e}"));
+    let mut sprites = Sprites {
+        tile_map,
+        player: SpriteSheet {
+            texture: player,
+            animations: Rc::new(animations),
+        },
+        vehicles,
+        weapon,
+    };
+    let seed = seed_from_args();
+    println!("seed {seed}");
+    let map = Map::generate_city(sprites.tile_map.clone(), 256, 4);
+    let mut recorder = Some(Recorder::new(seed, map.source.clone()));
+    let mut city_map = CityMap::new(&map);
+    let mut gps = Gps::new(&map);
+    let mut missions = Missions::load(&mission_dir).unwrap_or_else(|e| panic!("{e}"));
+    let scripts = Scripts::load(&script_dir).unwrap_or_else(|e| panic!("{e}"));
+    let mut game = Game::new(map, seed, &sprites, Missions::new(missions.defs.clone()), scripts);
+    log_events(&mut game);
+    #[cfg(feature = "audio")]
+    let sounds = Rc::new(std::cell::RefCell::new(Vec::new()));
+    #[cfg(feature = "audio")]
+    queue_sounds(&mut game, &sounds);
+    let mut input = TickInput::default();
+    let mut accumulator = 0.;
+
+    let mut bindings = Bindings::load_or_default();
+    let mut rebind_screen = RebindScreen::default();
+    let mut hud = Hud::default();
+    #[cfg(feature = "gamepad")]
+    let mut gamepads = autotheft2d::gamepad::Gamepads::new()
+        .map_err(|e| println!("{e}"))
+        .ok();
+
+    let mut save_slot = 0;
     loop {
-        controller.handle_key_inputs(&mut player);
-        clear_background(DARKGREEN);
-        world.map.draw(player.pos, 5);
+        if cfg!(debug_assertions) {
+            for name in assets.hot_reload().await {
+                match reload_data(&name, &mut assets, &mut sprites, &mut missions, &mut game) {
+                    // The replay would run with the data it is loaded with, not what was played
+                    Ok(true) if recorder.is_some() => {
+                        recorder = None;
+                        println!("input recording stopped");
+                    }
+                    Ok(_) => {}
+                    Err(e) => println!("{e}"),
+                }
+            }
+        }
+        if rebind_screen.open {
+            // The game is paused while the controls are being changed
+            rebind_screen.update(&mut bindings);
+            rebind_screen.draw(&bindings);
+            next_frame().await;
+            continue;
+        }
+        if city_map.open {
+            // The game is also paused while looking at the map
+            city_map.update(&bindings);
+            city_map.draw(game.player.pos, &minimap::blips(&game, city_map.waypoint), gps.route.as_deref(), &bindings);
+            next_frame().await;
+            continue;
+        }
+        if bindings.is_pressed(Action::Controls) {
+            rebind_screen.open = true;
+        }
+        if bindings.is_pressed(Action::ToggleHud) {
+            hud.toggle();
+        }
+        if bindings.is_pressed(Action::Map) {
+            city_map.open_at(game.player.pos);
+        }
+        if bindings.is_pressed(Action::NextSaveSlot) {
+            save_slot = (save_slot + 1) % save::SAVE_SLOTS;
+            println!("save slot {save_slot}, used: {:?}", save::used_slots());
+        }
+        if bindings.is_pressed(Action::QuickSave) {
+            match SaveGame::capture(&game.world, &game.player, &game.scripts).and_then(|s| save::save_to_slot(save_slot, &s)) {
+                Ok(()) => println!("saved to slot {save_slot}"),
+                Err(e) => println!("{e}"),
+            }
+        }
+        if bindings.is_pressed(Action::QuickLoad) {
+            // Scripts are read again, so they can be changed without restarting the game
+            let loaded = save::load_from_slot(save_slot).and_then(|s| {
+                Game::from_save(&s, seed, &sprites, Missions::new(missions.defs.clone()), Scripts::load(&script_dir)?)
+            });
+            match loaded {
+                Ok(loaded) => {
+                    game = loaded;
+                    log_events(&mut game);
+                    #[cfg(feature = "audio")]
+                    queue_sounds(&mut game, &sounds);
+                    city_map = CityMap::new(&game.world.map);
+                    gps = Gps::new(&game.world.map);
+                    // Replays always start from a new game
+                    recorder = None;
+                    println!("loaded slot {save_slot}, input recording stopped");
+                }
+                Err(e) => println!("{e}"),
+            }
+        }
+        if bindings.is_pressed(Action::SaveReplay) {
+            match recorder.as_ref().map(|r| r.finish(&game).save()) {
+                Some(Ok(path)) => println!("saved replay to {}", path.display()),
+                Some(Err(e)) => println!("{e}"),
+                None => println!("not recording"),
+            }
+        }
 
-        if is_key_pressed(KeyCode::F) {
-            if let Some(vehicle) = player.in_vehicle.clone() {
-                player.leave_vehicle(vehicle);
-                println!("left vehicle")
-            } else {
-                if let Some(vehicle) = world.vehicles
-                    .iter()
-                    .find(|v| v.borrow().position().distance(player.pos) < 100.)
-                {
-                    player.enter_vehicle(vehicle.clone()); // increase ref-count.
-                    println!("entered vehicle");
+        input.direction = ControllerDirectionState::from_bindings(&bindings);
+        input.handbrake = bindings.is_down(Action::Handbrake);
+        input.use_vehicle |= bindings.is_pressed(Action::EnterVehicle);
+        input.board_as_passenger |= bindings.is_pressed(Action::EnterAsPassenger);
+        input.shift_up |= bindings.is_pressed(Action::ShiftUp);
+        input.shift_down |= bindings.is_pressed(Action::ShiftDown);
+        input.next_weapon |= bindings.is_pressed(Action::WeaponWheel);
+        input.switch_control_scheme |= bindings.is_pressed(Action::ControlScheme);
+        if bindings.is_pressed(Action::Fire) {
+            input.fire = Some(mouse_direction().into());
+        }
+        #[cfg(feature = "gamepad")]
+        if let Some(gamepads) = &mut gamepads {
+            gamepads.poll();
+            input.analog = gamepads.analog(&bindings);
+            if input.analog.is_some() {
+                input.handbrake |= gamepads.is_down(Action::Handbrake);
+                input.use_vehicle |= gamepads.is_pressed(Action::EnterVehicle);
+                input.board_as_passenger |= gamepads.is_pressed(Action::EnterAsPassenger);
+                input.shift_up |= gamepads.is_pressed(Action::ShiftUp);
+                input.shift_down |= gamepads.is_pressed(Action::ShiftDown);
+                input.next_weapon |= gamepads.is_pressed(Action::WeaponWheel);
+                input.switch_control_scheme |= gamepads.is_pressed(Action::ControlScheme);
+                if gamepads.is_pressed(Action::Fire) {
+                    input.fire = Some(game.player.facing.as_vector().into());
                 }
             }
         }
-        if is_mouse_button_pressed(MouseButton::Left) {
-            weapon.shoot(mouse_direction(), &world.collideables);
+        accumulator += get_frame_time().min(MAX_FRAME_TIME);
+        while accumulator >= TICK {
+            if let Some(recorder) = &mut recorder {
+                recorder.record(&input);
+            }
+            game.tick(&input);
+            input.clear_actions();
+            accumulator -= TICK;
         }
+        #[cfg(feature = "audio")]
+        for name in sounds.borrow_mut().drain(..) {
+            match assets.sound_handle(name) {
+                Ok(sound) => macroquad::audio::play_sound_once(assets.sound(sound)),
+                Err(e) => println!("{e}"),
+            }
+        }
+
+        if city_map.waypoint.is_some_and(|w| w.distance(game.player.pos) < gps::ARRIVAL_DISTANCE) {
+            city_map.waypoint = None;
+        }
+        // A waypoint set by the player goes before the mission
+        let destination = city_map.waypoint.or_else(|| game.missions.marker());
+        gps.update(&game.world.roads, &game.world.map, &game.player, destination);
+
+        let Game { world, player, .. } = &game;
+        clear_background(DARKGREEN);
+        world.map.draw(player.pos, 5);
+        world.intersections.draw(player.pos);
+        world.crowd.draw(player.pos);
+        world.police.draw(player.pos);
+        gps.draw(player.pos);
+        if cfg!(debug_assertions) {
+            world.triggers.draw(player.pos);
+        }
+        game.missions.draw(player, player.pos);
         player.draw();
-        weapon.pos = player.pos;
-        weapon.pos.y += 30.;
-        weapon.draw_at_world_space(player.pos);
-        player.update();
-        world.vehicles.iter().for_each(|v| {
-            let mut v = v.borrow_mut();
-            v.draw_at_world_space(player.pos);
-            v.update();
-        });
-        if let Some(vehicle) = player.in_vehicle.clone() {
-            let vehicle = vehicle.borrow();
-            let velocity = vehicle.velocity;
-            let speed_text = &format!("{:.2} km/h", velocity * 3.6);
-            draw_text(
-                speed_text,
-                screen_width() / 2.,
-                screen_height() - 140.,
-                50.,
-                WHITE,
-            );
-            draw_text(
-                &format!("Acceleration: {:.10}", vehicle.acceleration),
-                screen_width() / 2.,
-                screen_height(),
-                50.,
-                WHITE,
-            );
+        if let Some(weapon) = player.weapon().filter(|_| player.in_vehicle.is_none()) {
+            weapon.draw_at_world_space(player.pos);
+        }
+        for vehicle in &world.vehicles {
+            vehicle.borrow().draw_at_world_space(player.pos);
         }
-        
-        
-        //pos.normalize()
-        draw_text(
-            &format!("pos: {:.2?}", player.pos),
-            screen_width() / 2.,
-            screen_height() - 40.,
-            50.,
-            WHITE,
-        );
-        draw_text(
-            &format!("pos (tile): {:.2?}", world.map.to_tile_index_pos(player.pos)),
-            screen_width() / 2.,
-            screen_height() - 70.,
-            50.,
-            WHITE,
-        );
         let center: Vec2 = (screen_width() / 2., screen_height() / 2.).into();
         
         draw_vector(center, mouse_direction(), 100., MAGENTA);
-        println!("{:?}", mouse_direction());
+        hud.draw(&game);
+        if hud.visible {
+            let heading = match &player.in_vehicle {
+                Some(vehicle) => vehicle.borrow().rotation,
+                None => player.facing.as_vector(),
+            };
+            city_map.draw_minimap(
+                player.pos,
+                heading,
+                &minimap::blips(&game, city_map.waypoint),
+                gps.route.as_deref(),
+            );
+        }
         next_frame().await
     }
     
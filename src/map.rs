@@ -1,59 +1,209 @@
 use macroquad::prelude::*;
+use nanoserde::{DeJson, SerJson};
 use ndarray::Array2;
 
-use crate::{draw::Draw, util::SpiralIterator};
+use crate::{draw::Draw, util::SpiralIterator, Direction};
 
 pub const TILE_TEX_SIZE: f32 = 32.;
 pub const TILE_TEXTURE_SCALING_FAC: f32 = 16.;
 pub const TILE_TEX_SIZE_SCALED: f32 = TILE_TEX_SIZE * TILE_TEXTURE_SCALING_FAC;
 
+/// Where the layout of a map came from, so it can be rebuilt, e.g. when loading a save.
+#[derive(Debug, Clone, PartialEq, Eq, SerJson, DeJson)]
+pub enum MapSource {
+    /// Crossings everywhere, see [`Map::new`].
+    Crossings { size: usize },
+    City { size: usize, block_size: usize },
+    File(String),
+    /// Built by code from arbitrary tile variants. Can't be rebuilt.
+    Custom,
+}
+
 pub struct Map {
     tiles: Array2<Tile>,
     world_size: usize,
+    pub source: MapSource,
 }
 
 impl Map {
     pub fn new(tile_map_texture: Texture2D, world_length_tiles: usize) -> Self {
-        let tile = Tile::new(
-            tile_map_texture,
-            (0., 0.).into(),
-            (true, true, true, true).into(),
+        let variants = Array2::from_elem(
+            (world_length_tiles, world_length_tiles),
+            TileVariant::full_crossing(),
         );
-        let mut tiles = Array2::from_elem((world_length_tiles, world_length_tiles), tile);
-        for ((x_tile, y_tile), tile) in tiles.indexed_iter_mut() {
-            let x_tile = x_tile as i32 - world_length_tiles as i32 / 2;
-            let y_tile = y_tile as i32 - world_length_tiles as i32 / 2;
-            tile.pos = (
-                x_tile as f32 * Tile::texture_size_scaled(),
-                y_tile as f32 * Tile::texture_size_scaled(),
+        let mut map = Map::from_variants(tile_map_texture, variants);
+        map.source = MapSource::Crossings { size: world_length_tiles };
+        map
+    }
+
+    /// Rebuilds a map from where it originally came from.
+    pub fn from_source(tile_map_texture: Texture2D, source: &MapSource) -> Result<Self, String> {
+        match source {
+            MapSource::Crossings { size } => Ok(Map::new(tile_map_texture, *size)),
+            MapSource::City { size, block_size } => Ok(Map::generate_city(tile_map_texture, *size, *block_size)),
+            MapSource::File(path) => Map::load(tile_map_texture, path),
+            MapSource::Custom => Err("Map was not built from a file or generator".to_string()),
+        }
+    }
+
+    /// Loads a map from a text file with one line per row of tiles.
+    /// Each tile is a hex digit of the directions its road leads to (1 north, 2 east, 4 south, 8 west),
+    /// or `.` for no road. Only the digits with a tile to draw are allowed, that is straights `5` and `a`,
+    /// curves `3`, `6`, `9` and `c`, T-junctions `7`, `b`, `d` and `e` and crossings `f`.
+    pub fn load(tile_map_texture: Texture2D, path: &str) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("Failed to read map {path}: {e}"))?;
+        let rows: Vec<&str> = text.lines().filter(|line| !line.trim().is_empty()).collect();
+        let size = rows.len();
+        let mut variants = Array2::from_elem((size, size), TileVariant::empty());
+        for (y, row) in rows.iter().enumerate() {
+            let row: Vec<char> = row.trim().chars().collect();
+            if row.len() != size {
+                return Err(format!("Map {path} has to be square, row {y} has {} tiles instead of {size}", row.len()));
+            }
+            for (x, c) in row.into_iter().enumerate() {
+                let bits = match c {
+                    '.' => 0,
+                    c => c
+                        .to_digit(16)
+                        .ok_or_else(|| format!("Invalid tile '{c}' in map {path} at {x}, {y}"))?,
+                };
+                let variant: TileVariant = (bits & 1 != 0, bits & 2 != 0, bits & 4 != 0, bits & 8 != 0).into();
+                if c != '.' && (!variant.is_road() || variant.texture_x_offset().is_none()) {
+                    return Err(format!("Unsupported tile '{c}' at {x}, {y}"));
+                }
+                variants[(x, y)] = variant;
+            }
+        }
+        let mut map = Map::from_variants(tile_map_texture, variants);
+        map.source = MapSource::File(path.to_string());
+        Ok(map)
+    }
+
+    /// Builds a square map from a grid of tile variants, indexed by `(x, y)`.
+    /// `Panics` if the grid is not square.
+    pub fn from_variants(tile_map_texture: Texture2D, variants: Array2<TileVariant>) -> Self {
+        let (width, height) = variants.dim();
+        assert_eq!(width, height, "Map has to be square");
+        let world_length_tiles = width;
+        let tiles = Array2::from_shape_fn((world_length_tiles, world_length_tiles), |(x, y)| {
+            Tile::new(
+                tile_map_texture.clone(),
+                tile_center(world_length_tiles, (x, y)),
+                variants[(x, y)],
             )
-                .into();
+        });
+        Map {
+            tiles,
+            world_size: world_length_tiles,
+            source: MapSource::Custom,
         }
-        Map { tiles, world_size: world_length_tiles }
     }
-    
+
+    /// Generates a city laid out as a regular grid of blocks.
+    /// Every `block_size`th row and column of tiles is a road, everything in between is empty.
+    pub fn generate_city(tile_map_texture: Texture2D, world_length_tiles: usize, block_size: usize) -> Self {
+        let last_road = (world_length_tiles - 1) / block_size * block_size;
+        let is_road = |x: usize, y: usize| x % block_size == 0 || y % block_size == 0;
+        let variants = Array2::from_shape_fn((world_length_tiles, world_length_tiles), |(x, y)| {
+            if x > last_road || y > last_road || !is_road(x, y) {
+                return TileVariant::empty();
+            }
+            let vertical = x % block_size == 0;
+            let horizontal = y % block_size == 0;
+            (
+                vertical && y > 0,
+                horizontal && x < last_road,
+                vertical && y < last_road,
+                horizontal && x > 0,
+            )
+                .into()
+        });
+        let mut map = Map::from_variants(tile_map_texture, variants);
+        map.source = MapSource::City {
+            size: world_length_tiles,
+            block_size,
+        };
+        map
+    }
+
     // tpos_world = (tpos - world_len / 2) * tex_scaled
     // tpos = tpos_world / tex_scaled + world_len / 2
     pub fn to_tile_index_pos(&self, world_pos: Vec2) -> (usize, usize) {
-        let x = (world_pos.x / Tile::texture_size_scaled()) as isize + self.world_size as isize / 2;
-        let y = (world_pos.y / Tile::texture_size_scaled()) as isize + self.world_size as isize / 2;
+        let x = (world_pos.x / Tile::texture_size_scaled()).round() as isize + self.world_size as isize / 2;
+        let y = (world_pos.y / Tile::texture_size_scaled()).round() as isize + self.world_size as isize / 2;
         (x as usize, y as usize)
     }
-    
+
+    /// World position of the center of the tile at `index`.
+    pub fn tile_center(&self, index: (usize, usize)) -> Vec2 {
+        tile_center(self.world_size, index)
+    }
+
     pub fn draw(&self, player_pos: Vec2, radius: usize) -> Option<&Tile> {
         let center = self.to_tile_index_pos(player_pos);
         let spiral = SpiralIterator::new(center);
-        self.tiles.get(center).map(|tile| tile.draw_at_world_space(player_pos));
+        if let Some(tile) = self.tiles.get(center) {
+            tile.draw_at_world_space(player_pos);
+        }
         spiral.take(radius * radius).for_each(|pos| {
-            self.tiles.get(pos).map(|tile| tile.draw_at_world_space(player_pos));
+            if let Some(tile) = self.tiles.get(pos) {
+                tile.draw_at_world_space(player_pos);
+            }
         });
-        
+
         self.tiles.get(self.to_tile_index_pos(player_pos))
     }
-    
+
     pub fn get_tile(&self, pos: (usize, usize)) -> Option<&Tile> {
         self.tiles.get(pos)
     }
+
+    /// Length of one side of the map in tiles.
+    pub fn world_size(&self) -> usize {
+        self.world_size
+    }
+
+    pub fn indexed_tiles(&self) -> impl Iterator<Item = ((usize, usize), &Tile)> {
+        self.tiles.indexed_iter()
+    }
+
+    /// Index of the tile next to `pos` in the given cardinal direction.
+    /// Returns `None` for diagonal directions or when the neighbour would be off the map.
+    pub fn neighbour(&self, pos: (usize, usize), direction: Direction) -> Option<(usize, usize)> {
+        let (x, y) = pos;
+        let neighbour = match direction {
+            Direction::North => (x, y.checked_sub(1)?),
+            Direction::East => (x + 1, y),
+            Direction::South => (x, y + 1),
+            Direction::West => (x.checked_sub(1)?, y),
+            _ => return None,
+        };
+        self.tiles.get(neighbour).map(|_| neighbour)
+    }
+
+    /// Whether the roads of the tile at `pos` and its neighbour in `direction` are joined.
+    /// Both tiles have to agree for the connection to count.
+    pub fn road_connects(&self, pos: (usize, usize), direction: Direction) -> bool {
+        let Some(tile) = self.tiles.get(pos) else {
+            return false;
+        };
+        if !tile.variant.connects(direction) {
+            return false;
+        }
+        self.neighbour(pos, direction)
+            .and_then(|neighbour| self.tiles.get(neighbour))
+            .is_some_and(|neighbour| neighbour.variant.connects(direction.opposite()))
+    }
+}
+
+fn tile_center(world_length_tiles: usize, (x, y): (usize, usize)) -> Vec2 {
+    let x_tile = x as i32 - world_length_tiles as i32 / 2;
+    let y_tile = y as i32 - world_length_tiles as i32 / 2;
+    (
+        x_tile as f32 * Tile::texture_size_scaled(),
+        y_tile as f32 * Tile::texture_size_scaled(),
+    )
+        .into()
 }
 
 #[derive(Clone)]
@@ -61,17 +211,22 @@ pub struct Tile {
     pos: Vec2,
     texture: Texture2D,
     texture_x_offset: f32,
+    variant: TileVariant,
 }
 
 impl Tile {
     pub fn new(texture: Texture2D, pos: Vec2, variant: TileVariant) -> Self {
-        texture.set_filter(FilterMode::Nearest);
         Tile {
             texture,
-            texture_x_offset: variant.get_x_texture_offset(),
+            texture_x_offset: variant.texture_x_offset().expect("Nonexistent tile variant"),
             pos,
+            variant,
         }
     }
+
+    pub fn variant(&self) -> TileVariant {
+        self.variant
+    }
 }
 
 impl Draw for Tile {
@@ -111,22 +266,45 @@ impl Draw for Tile {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct TileVariant {
-    north: bool,
-    east: bool,
-    south: bool,
-    west: bool,
+    pub north: bool,
+    pub east: bool,
+    pub south: bool,
+    pub west: bool,
 }
 
 impl TileVariant {
-    fn empty() -> Self {
+    pub fn empty() -> Self {
         (false, false, false, false).into()
     }
-    fn full_crossing() -> Self {
+    pub fn full_crossing() -> Self {
         (true, true, true, true).into()
     }
-    fn get_x_texture_offset(&self) -> f32 {
-        match self {
+
+    /// Whether the road on this tile leads towards `direction`. Diagonals never connect.
+    pub fn connects(&self, direction: Direction) -> bool {
+        match direction {
+            Direction::North => self.north,
+            Direction::East => self.east,
+            Direction::South => self.south,
+            Direction::West => self.west,
+            _ => false,
+        }
+    }
+
+    pub fn is_road(&self) -> bool {
+        self.north || self.east || self.south || self.west
+    }
+
+    /// Crossings and T-junctions, i.e. tiles where more than two roads meet.
+    pub fn is_junction(&self) -> bool {
+        [self.north, self.east, self.south, self.west].iter().filter(|c| **c).count() > 2
+    }
+
+    /// Where the tile is in the tile map texture, or `None` if there is no tile for this variant.
+    fn texture_x_offset(&self) -> Option<f32> {
+        let offset = match self {
             TileVariant {
                 north: true,
                 east: false,
@@ -199,8 +377,9 @@ impl TileVariant {
                 south: false,
                 west: false,
             } => TILE_TEX_SIZE * 11.,
-            _ => panic!("Nonexistent tile variant"),
-        }
+            _ => return None,
+        };
+        Some(offset)
     }
 }
 
@@ -214,3 +393,37 @@ impl From<(bool, bool, bool, bool)> for TileVariant {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use macroquad::miniquad::{RawId, TextureId};
+
+    use super::*;
+
+    fn load(name: &str, text: &str) -> Result<Map, String> {
+        let path = std::env::temp_dir().join(format!("autotheft2d-{name}.txt"));
+        std::fs::write(&path, text).unwrap();
+        let texture = Texture2D::from_miniquad_texture(TextureId::from_raw_id(RawId::OpenGl(0)));
+        let map = Map::load(texture, path.to_str().unwrap());
+        std::fs::remove_file(&path).unwrap();
+        map
+    }
+
+    #[test]
+    fn loads_drawable_tiles() {
+        let map = load("drawable", "6e.\n7f9\n3b.\n").unwrap();
+        assert_eq!(map.world_size(), 3);
+        assert_eq!(map.get_tile((1, 1)).unwrap().variant(), TileVariant::full_crossing());
+        assert!(!map.get_tile((2, 0)).unwrap().variant().is_road());
+    }
+
+    #[test]
+    fn rejects_dead_ends() {
+        assert_eq!(load("dead-end", "6c\n2.\n").err().unwrap(), "Unsupported tile '2' at 0, 1");
+    }
+
+    #[test]
+    fn rejects_zero_for_no_road() {
+        assert!(load("zero", "0").is_err());
+    }
+}
@@ -0,0 +1,358 @@
+use macroquad::prelude::*;
+
+use crate::{
+    draw::Draw,
+    game::Game,
+    gps::ROUTE_COLOR,
+    input::{Action, Bindings},
+    map::{Map, TILE_TEX_SIZE_SCALED},
+    navigation::Route,
+    Direction,
+};
+
+/// Pixels per tile in the map texture.
+const TILE_PIXELS: usize = 4;
+/// Side length of the minimap on a window 720 pixels high.
+const MINIMAP_SIZE: f32 = 200.;
+const REFERENCE_HEIGHT: f32 = 720.;
+const MARGIN: f32 = 20.;
+/// World units across the minimap.
+const MINIMAP_RANGE: f32 = 8000.;
+const BLIP_RADIUS: f32 = 5.;
+/// Screen pixels per world unit on the full-screen map, and how far it zooms in and out.
+const DEFAULT_MAP_ZOOM: f32 = 0.01;
+const MIN_MAP_ZOOM: f32 = 0.002;
+const MAX_MAP_ZOOM: f32 = 0.05;
+/// Screen pixels per second the full-screen map pans with the arrow keys.
+const PAN_SPEED: f32 = 600.;
+
+const GROUND: Color = Color::new(0.16, 0.3, 0.18, 1.);
+const ROAD: Color = Color::new(0.6, 0.6, 0.6, 1.);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BlipKind {
+    /// Where a mission can be started.
+    Mission,
+    /// Where the current mission objective is.
+    Objective,
+    Police,
+    Shop,
+    Waypoint,
+}
+
+impl BlipKind {
+    pub fn from_name(name: &str) -> Option<BlipKind> {
+        match name {
+            "mission" => Some(BlipKind::Mission),
+            "objective" => Some(BlipKind::Objective),
+            "police" => Some(BlipKind::Police),
+            "shop" => Some(BlipKind::Shop),
+            "waypoint" => Some(BlipKind::Waypoint),
+            _ => None,
+        }
+    }
+
+    fn color(self) -> Color {
+        match self {
+            BlipKind::Mission => YELLOW,
+            BlipKind::Objective => GOLD,
+            BlipKind::Police => BLUE,
+            BlipKind::Shop => GREEN,
+            BlipKind::Waypoint => MAGENTA,
+        }
+    }
+
+    /// Whether the blip stays on the edge of the minimap when it is out of range.
+    fn pinned(self) -> bool {
+        matches!(self, BlipKind::Mission | BlipKind::Objective | BlipKind::Waypoint)
+    }
+}
+
+/// A marker on the maps.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Blip {
+    /// Lets whoever placed the blip find it again, empty for blips made up every frame.
+    pub name: String,
+    pub kind: BlipKind,
+    pub pos: Vec2,
+}
+
+impl Blip {
+    pub fn new(kind: BlipKind, pos: Vec2) -> Self {
+        Blip {
+            name: String::new(),
+            kind,
+            pos,
+        }
+    }
+}
+
+/// Blips for everything worth showing on the maps right now.
+pub fn blips(game: &Game, waypoint: Option<Vec2>) -> Vec<Blip> {
+    let mut blips = game.world.blips.clone();
+    if game.missions.active.is_none() {
+        blips.extend(
+            game.missions
+                .available(&game.player)
+                .map(|def| Blip::new(BlipKind::Mission, Vec2::from(def.start))),
+        );
+    }
+    if let Some(marker) = game.missions.marker() {
+        blips.push(Blip::new(BlipKind::Objective, marker));
+    }
+    blips.extend(
+        game.world
+            .police
+            .units
+            .iter()
+            .map(|unit| Blip::new(BlipKind::Police, unit.vehicle.borrow().position())),
+    );
+    blips.extend(
+        game.world
+            .police
+            .officers
+            .iter()
+            .filter(|officer| !officer.body.is_down())
+            .map(|officer| Blip::new(BlipKind::Police, officer.body.pos)),
+    );
+    if let Some(waypoint) = waypoint {
+        blips.push(Blip::new(BlipKind::Waypoint, waypoint));
+    }
+    blips
+}
+
+/// The whole city drawn small: a rotating minimap around the player and a full-screen map to look around and
+/// set a waypoint on.
+pub struct CityMap {
+    texture: Texture2D,
+    world_size: usize,
+    pub open: bool,
+    /// World position in the middle of the full-screen map.
+    center: Vec2,
+    zoom: f32,
+    pub waypoint: Option<Vec2>,
+}
+
+impl CityMap {
+    pub fn new(map: &Map) -> Self {
+        let texture = Texture2D::from_image(&map_image(map));
+        texture.set_filter(FilterMode::Nearest);
+        CityMap {
+            texture,
+            world_size: map.world_size(),
+            open: false,
+            center: Vec2::ZERO,
+            zoom: DEFAULT_MAP_ZOOM,
+            waypoint: None,
+        }
+    }
+
+    /// Opens the full-screen map centered on the player.
+    pub fn open_at(&mut self, player_pos: Vec2) {
+        self.open = true;
+        self.center = player_pos;
+    }
+
+    /// Panning with the movement controls or by dragging, zooming with the mouse wheel,
+    /// right click to set or clear the waypoint and the map control again to close.
+    pub fn update(&mut self, bindings: &Bindings) {
+        let dt = get_frame_time();
+        let mut pan = Vec2::ZERO;
+        if bindings.is_down(Action::MoveLeft) {
+            pan.x -= 1.;
+        }
+        if bindings.is_down(Action::MoveRight) {
+            pan.x += 1.;
+        }
+        if bindings.is_down(Action::MoveUp) {
+            pan.y -= 1.;
+        }
+        if bindings.is_down(Action::MoveDown) {
+            pan.y += 1.;
+        }
+        self.center += pan * PAN_SPEED * dt / self.zoom;
+        if is_mouse_button_down(MouseButton::Left) {
+            // The delta is from the current to the last position, in halves of the screen
+            self.center += mouse_delta_position() * Vec2::new(screen_width(), screen_height()) / 2. / self.zoom;
+        }
+        let (_, wheel) = mouse_wheel();
+        if wheel != 0. {
+            let factor = if wheel > 0. { 1.25 } else { 0.8 };
+            self.zoom = (self.zoom * factor).clamp(MIN_MAP_ZOOM, MAX_MAP_ZOOM);
+        }
+        let half = self.world_size as f32 * TILE_TEX_SIZE_SCALED / 2.;
+        self.center = self.center.clamp(Vec2::splat(-half), Vec2::splat(half));
+
+        if is_mouse_button_pressed(MouseButton::Right) {
+            let clicked = self.screen_to_world(Vec2::from(mouse_position()));
+            self.waypoint = match self.waypoint {
+                // Clicking the waypoint again removes it
+                Some(waypoint) if (waypoint - clicked).length() * self.zoom < BLIP_RADIUS * 3. => None,
+                _ => Some(clicked),
+            };
+        }
+        if bindings.is_pressed(Action::Map) {
+            self.open = false;
+        }
+    }
+
+    fn screen_to_world(&self, screen: Vec2) -> Vec2 {
+        self.center + (screen - Vec2::new(screen_width(), screen_height()) / 2.) / self.zoom
+    }
+
+    fn world_to_screen(&self, world: Vec2) -> Vec2 {
+        (world - self.center) * self.zoom + Vec2::new(screen_width(), screen_height()) / 2.
+    }
+
+    /// World position of the top left corner of the map texture, and its size in world units.
+    fn texture_rect(&self) -> Rect {
+        let size = self.world_size as f32 * TILE_TEX_SIZE_SCALED;
+        let corner = -(self.world_size as f32 / 2. + 0.5) * TILE_TEX_SIZE_SCALED;
+        Rect::new(corner, corner, size, size)
+    }
+
+    pub fn draw(&self, player_pos: Vec2, blips: &[Blip], route: Option<&Route>, bindings: &Bindings) {
+        clear_background(BLACK);
+        let rect = self.texture_rect();
+        let corner = self.world_to_screen(rect.point());
+        draw_texture_ex(
+            &self.texture,
+            corner.x,
+            corner.y,
+            WHITE,
+            DrawTextureParams {
+                dest_size: Some(rect.size() * self.zoom),
+                ..Default::default()
+            },
+        );
+        if let Some(route) = route {
+            for w in route.waypoints.windows(2) {
+                let (a, b) = (self.world_to_screen(w[0]), self.world_to_screen(w[1]));
+                draw_line(a.x, a.y, b.x, b.y, 4., ROUTE_COLOR);
+            }
+        }
+        for blip in blips {
+            let pos = self.world_to_screen(blip.pos);
+            draw_blip(pos, blip.kind.color(), 1.5);
+        }
+        let player = self.world_to_screen(player_pos);
+        draw_circle(player.x, player.y, BLIP_RADIUS * 1.5, WHITE);
+        let close = bindings.get(Action::Map).first().map_or("?", |binding| binding.name());
+        draw_text(
+            &format!("move/drag: pan   wheel: zoom   right click: waypoint   {close}: close"),
+            20.,
+            screen_height() - 20.,
+            24.,
+            WHITE,
+        );
+    }
+
+    /// A square map around the player in the bottom left corner, turned so `heading` points up.
+    pub fn draw_minimap(&self, player_pos: Vec2, heading: Vec2, blips: &[Blip], route: Option<&Route>) {
+        let scale = screen_height() / REFERENCE_HEIGHT;
+        let size = MINIMAP_SIZE * scale;
+        // Above the health and armor bars
+        let frame = Rect::new(
+            MARGIN * scale,
+            screen_height() - MARGIN * scale * 4. - size,
+            size,
+            size,
+        );
+        draw_rectangle(frame.x - 3., frame.y - 3., frame.w + 6., frame.h + 6., BLACK);
+
+        // Turning the world so the heading points up, which is -90°
+        let turn = -core::f32::consts::FRAC_PI_2 - heading.to_angle();
+        let camera = Camera2D {
+            target: player_pos,
+            zoom: Vec2::splat(2. / MINIMAP_RANGE),
+            rotation: turn.to_degrees(),
+            viewport: Some((
+                frame.x as i32,
+                (screen_height() - frame.y - frame.h) as i32,
+                frame.w as i32,
+                frame.h as i32,
+            )),
+            ..Default::default()
+        };
+        set_camera(&camera);
+        let rect = self.texture_rect();
+        draw_texture_ex(
+            &self.texture,
+            rect.x,
+            rect.y,
+            WHITE,
+            DrawTextureParams {
+                dest_size: Some(rect.size()),
+                ..Default::default()
+            },
+        );
+        if let Some(route) = route {
+            let thickness = MINIMAP_RANGE / 60.;
+            for w in route.waypoints.windows(2) {
+                draw_line(w[0].x, w[0].y, w[1].x, w[1].y, thickness, ROUTE_COLOR);
+            }
+        }
+        set_default_camera();
+
+        let center = frame.center();
+        let world_to_minimap = |pos: Vec2| Vec2::from_angle(turn).rotate(pos - player_pos) * size / MINIMAP_RANGE;
+        let half = size / 2. - BLIP_RADIUS * 2.;
+        for blip in blips {
+            let offset = world_to_minimap(blip.pos);
+            let outside = offset.x.abs() > half || offset.y.abs() > half;
+            if outside && !blip.kind.pinned() {
+                continue;
+            }
+            let offset = offset.clamp(Vec2::splat(-half), Vec2::splat(half));
+            draw_blip(center + offset, blip.kind.color(), 1.);
+        }
+        // North, wherever it ended up
+        let north = center + Vec2::from_angle(turn).rotate(Direction::North.as_vector()) * (half - 4.);
+        draw_text("N", north.x - 6., north.y + 6., 20. * scale, WHITE);
+        draw_triangle(
+            center + Vec2::new(0., -9.),
+            center + Vec2::new(-6., 7.),
+            center + Vec2::new(6., 7.),
+            WHITE,
+        );
+    }
+}
+
+fn draw_blip(pos: Vec2, color: Color, scale: f32) {
+    draw_circle(pos.x, pos.y, BLIP_RADIUS * scale + 1.5, BLACK);
+    draw_circle(pos.x, pos.y, BLIP_RADIUS * scale, color);
+}
+
+/// The map with `TILE_PIXELS` per tile: the roads of each tile reaching out from its center to the sides it connects.
+fn map_image(map: &Map) -> Image {
+    let size = (map.world_size() * TILE_PIXELS) as u16;
+    let mut image = Image::gen_image_color(size, size, GROUND);
+    let (low, high) = (TILE_PIXELS / 4, TILE_PIXELS * 3 / 4);
+    for ((x, y), tile) in map.indexed_tiles() {
+        let variant = tile.variant();
+        if !variant.is_road() {
+            continue;
+        }
+        let mut paint = |xs: std::ops::Range<usize>, ys: std::ops::Range<usize>| {
+            for px in xs {
+                for py in ys.clone() {
+                    image.set_pixel((x * TILE_PIXELS + px) as u32, (y * TILE_PIXELS + py) as u32, ROAD);
+                }
+            }
+        };
+        paint(low..high, low..high);
+        if variant.north {
+            paint(low..high, 0..low);
+        }
+        if variant.south {
+            paint(low..high, high..TILE_PIXELS);
+        }
+        if variant.west {
+            paint(0..low, low..high);
+        }
+        if variant.east {
+            paint(high..TILE_PIXELS, low..high);
+        }
+    }
+    image
+}
@@ -0,0 +1,318 @@
+// An `Objective`'s `time_limit` is optional, and the `DeJson` impl generated for it
+// trips this lint in a separate item an attribute on the struct can't cover.
+#![allow(clippy::question_mark)]
+
+use std::{cell::RefCell, fs, rc::Rc};
+
+use macroquad::prelude::*;
+use nanoserde::{DeJson, SerJson};
+
+use crate::{
+    draw::Draw,
+    event::GameEvent,
+    pedestrian::Pedestrian,
+    player::Player,
+    vehicle::Vehicle,
+    World,
+};
+
+/// How close the player has to walk to a mission's start marker to begin it.
+const START_RADIUS: f32 = 120.;
+/// A mission car with this much damage is a wreck.
+const WRECKED_DAMAGE: f32 = 800.;
+
+/// What the player has to do to finish one step of a mission.
+#[derive(Debug, Clone, SerJson, DeJson)]
+pub enum Goal {
+    /// Get within `radius` of `pos`, on foot or not.
+    GoTo { pos: [f32; 2], radius: f32 },
+    /// A car of `model` is parked at `pos`. Get in behind the wheel.
+    StealVehicle {
+        model: String,
+        pos: [f32; 2],
+        rotation: [f32; 2],
+        locked: bool,
+    },
+    /// Drive the mission car, or any car if there is none, to within `radius` of `pos`.
+    Deliver { pos: [f32; 2], radius: f32 },
+    /// Someone appears at `pos` who has to die.
+    Kill { pos: [f32; 2] },
+    /// The player gets at least `stars` and has to shake the police off.
+    LoseWanted { stars: u8 },
+    /// Stay alive for a while.
+    Survive { seconds: f32 },
+}
+
+#[derive(Debug, Clone, SerJson, DeJson)]
+pub struct Objective {
+    /// Shown to the player while the objective is active.
+    pub text: String,
+    pub goal: Goal,
+    /// Seconds to get it done, or the mission fails.
+    #[nserde(default)]
+    pub time_limit: Option<f32>,
+}
+
+/// A mission as designed in its data file.
+#[derive(Debug, Clone, SerJson, DeJson)]
+pub struct MissionDef {
+    /// Recorded in `Player::completed_missions` once finished.
+    pub id: String,
+    pub title: String,
+    /// The marker the player walks into to start the mission.
+    pub start: [f32; 2],
+    /// Missions that have to be finished before this one becomes available.
+    #[nserde(default)]
+    pub requires: Vec<String>,
+    pub objectives: Vec<Objective>,
+    /// Money paid on completion.
+    pub reward: u32,
+}
+
+/// The mission being played and how far along it is.
+pub struct ActiveMission {
+    pub def: usize,
+    pub objective: usize,
+    pub time_left: Option<f32>,
+    /// Time spent on the current objective.
+    pub elapsed: f32,
+    /// The car the mission is about, once one was spawned.
+    pub vehicle: Option<Rc<RefCell<Vehicle>>>,
+}
+
+/// All missions and the one being played.
+#[derive(Default)]
+pub struct Missions {
+    pub defs: Vec<MissionDef>,
+    pub active: Option<ActiveMission>,
+}
+
+impl Missions {
+    pub fn new(defs: Vec<MissionDef>) -> Self {
+        Missions { defs, active: None }
+    }
+
+    /// Reads every mission in `dir`, in file name order.
+    pub fn load(dir: &str) -> Result<Self, String> {
+        let mut paths: Vec<_> = fs::read_dir(dir)
+            .map_err(|e| format!("Failed to read {dir}: {e}"))?
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+            .collect();
+        paths.sort();
+
+        let mut missions = Missions::default();
+        for path in paths {
+            let text = fs::read_to_string(&path).map_err(|e| format!("Failed to read {}: {e}", path.display()))?;
+            let def =
+                MissionDef::deserialize_json(&text).map_err(|e| format!("Corrupt mission {}: {e}", path.display()))?;
+            if def.objectives.is_empty() {
+                return Err(format!("Mission {} has no objectives", def.id));
+            }
+            if missions.defs.iter().any(|d| d.id == def.id) {
+                return Err(format!("Mission {} is defined twice", def.id));
+            }
+            missions.defs.push(def);
+        }
+        Ok(missions)
+    }
+
+    /// Missions the player can start right now.
+    pub fn available<'a>(&'a self, player: &'a Player) -> impl Iterator<Item = &'a MissionDef> {
+        self.defs.iter().filter(move |def| {
+            !player.completed_missions.contains(&def.id)
+                && def.requires.iter().all(|id| player.completed_missions.contains(id))
+        })
+    }
+
+    pub fn current_objective(&self) -> Option<&Objective> {
+        let active = self.active.as_ref()?;
+        self.defs[active.def].objectives.get(active.objective)
+    }
+
+    /// Starts missions the player walks into, and checks the objectives and failure conditions of the active one.
+    /// Progress is published to the world's events.
+    pub fn update(&mut self, world: &mut World, player: &mut Player, dt: f32) {
+        let Some(active) = &mut self.active else {
+            if player.in_vehicle.is_some() {
+                return;
+            }
+            let start = self
+                .available(player)
+                .find(|def| Vec2::from(def.start).distance(player.pos) < START_RADIUS)
+                .map(|def| def.id.clone());
+            if let Some(id) = start {
+                let def = self.defs.iter().position(|d| d.id == id).unwrap();
+                world.events.publish(GameEvent::MissionStarted {
+                    id,
+                    title: self.defs[def].title.clone(),
+                });
+                self.active = Some(ActiveMission {
+                    def,
+                    objective: 0,
+                    time_left: None,
+                    elapsed: 0.,
+                    vehicle: None,
+                });
+                self.start_objective(world);
+            }
+            return;
+        };
+
+        let def = &self.defs[active.def];
+        let objective = &def.objectives[active.objective];
+        active.elapsed += dt;
+        if let Some(time_left) = &mut active.time_left {
+            *time_left -= dt;
+        }
+        let failure = if player.health <= 0. {
+            Some("You died")
+        } else if active.time_left.is_some_and(|t| t <= 0.) {
+            Some("Out of time")
+        } else if active.vehicle.as_ref().is_some_and(|v| v.borrow().damage >= WRECKED_DAMAGE) {
+            Some("The car is wrecked")
+        } else {
+            None
+        };
+        if let Some(reason) = failure {
+            self.fail(world, reason.to_string());
+            return;
+        }
+
+        let done = match &objective.goal {
+            Goal::GoTo { pos, radius } => player.pos.distance(Vec2::from(*pos)) < *radius,
+            Goal::StealVehicle { .. } => player
+                .driving()
+                .is_some_and(|v| active.vehicle.as_ref().is_some_and(|target| Rc::ptr_eq(v, target))),
+            Goal::Deliver { pos, radius } => player.driving().is_some_and(|v| {
+                let right_car = active.vehicle.as_ref().is_none_or(|target| Rc::ptr_eq(v, target));
+                right_car && v.borrow().position().distance(Vec2::from(*pos)) < *radius
+            }),
+            Goal::Kill { .. } => !world
+                .crowd
+                .pedestrians
+                .iter()
+                .any(|p| p.mission_target && p.health > 0.),
+            Goal::LoseWanted { .. } => world.wanted.stars() == 0,
+            Goal::Survive { seconds } => active.elapsed >= *seconds,
+        };
+        if !done {
+            return;
+        }
+        active.objective += 1;
+        if active.objective < def.objectives.len() {
+            self.start_objective(world);
+            return;
+        }
+        player.money += def.reward;
+        player.completed_missions.push(def.id.clone());
+        world.events.publish(GameEvent::MissionCompleted {
+            id: def.id.clone(),
+            reward: def.reward,
+        });
+        self.end(world);
+    }
+
+    /// Sets up the current objective, failing the mission when that can't be done.
+    fn start_objective(&mut self, world: &mut World) {
+        if let Err(reason) = self.begin_objective(world) {
+            println!("{reason}");
+            self.fail(world, reason);
+        }
+    }
+
+    /// Sets up what the current objective needs in the world.
+    fn begin_objective(&mut self, world: &mut World) -> Result<(), String> {
+        let Some(active) = self.active.as_mut() else {
+            return Ok(());
+        };
+        let objective = &self.defs[active.def].objectives[active.objective];
+        active.time_left = objective.time_limit;
+        active.elapsed = 0.;
+        match &objective.goal {
+            Goal::StealVehicle {
+                model,
+                pos,
+                rotation,
+                locked,
+            } => {
+                let vehicle = world.spawn_vehicle(model, Vec2::from(*pos), Vec2::from(*rotation))?;
+                vehicle.borrow_mut().locked = *locked;
+                active.vehicle = Some(vehicle);
+            }
+            Goal::Kill { pos } => {
+                // Without a target the objective would count as done right away
+                let sprite = world
+                    .crowd
+                    .sprites
+                    .first()
+                    .cloned()
+                    .ok_or("No pedestrian sprites to spawn the target with")?;
+                let mut target = Pedestrian::new(sprite, Vec2::from(*pos));
+                target.mission_target = true;
+                target.tint = RED;
+                world.crowd.add(target);
+            }
+            Goal::LoseWanted { stars } => {
+                if world.wanted.stars() < *stars {
+                    world.set_wanted_stars(*stars);
+                }
+            }
+            Goal::GoTo { .. } | Goal::Deliver { .. } | Goal::Survive { .. } => {}
+        }
+        world.events.publish(GameEvent::ObjectiveStarted {
+            text: objective.text.clone(),
+        });
+        Ok(())
+    }
+
+    fn fail(&mut self, world: &mut World, reason: String) {
+        if let Some(active) = &self.active {
+            world.events.publish(GameEvent::MissionFailed {
+                id: self.defs[active.def].id.clone(),
+                reason,
+            });
+        }
+        self.end(world);
+    }
+
+    /// Stops the active mission, leaving what it spawned to the world.
+    fn end(&mut self, world: &mut World) {
+        for pedestrian in &mut world.crowd.pedestrians {
+            pedestrian.mission_target = false;
+        }
+        self.active = None;
+    }
+
+    /// Where the current objective wants the player to go, if anywhere.
+    pub fn marker(&self) -> Option<Vec2> {
+        let active = self.active.as_ref()?;
+        match &self.current_objective()?.goal {
+            Goal::GoTo { pos, .. } | Goal::Deliver { pos, .. } | Goal::Kill { pos } => Some(Vec2::from(*pos)),
+            Goal::StealVehicle { pos, .. } => Some(
+                active
+                    .vehicle
+                    .as_ref()
+                    .map_or(Vec2::from(*pos), |v| v.borrow().position()),
+            ),
+            Goal::LoseWanted { .. } | Goal::Survive { .. } => None,
+        }
+    }
+
+    /// Start markers of the missions on offer, or the marker of the current objective.
+    pub fn draw(&self, player: &Player, camera_pos: Vec2) {
+        let offset = Vec2::new(screen_width(), screen_height()) / 2. - camera_pos;
+        if self.active.is_some() {
+            if let Some(marker) = self.marker() {
+                let pos = marker + offset;
+                draw_circle_lines(pos.x, pos.y, START_RADIUS, 6., GOLD);
+            }
+            return;
+        }
+        for def in self.available(player) {
+            let pos = Vec2::from(def.start) + offset;
+            draw_circle(pos.x, pos.y, START_RADIUS, Color::new(1., 0.85, 0., 0.4));
+        }
+    }
+}
+
@@ -0,0 +1,554 @@
+use std::{
+    cmp::Ordering,
+    collections::{BinaryHeap, HashMap, VecDeque},
+    rc::Rc,
+};
+
+use macroquad::prelude::*;
+use ndarray::Array2;
+
+use crate::{
+    map::{Map, TILE_TEX_SIZE_SCALED},
+    road::{EdgeId, LanePoint, RoadGraph},
+};
+
+/// How many routes are kept around before the oldest ones get evicted.
+pub const ROUTE_CACHE_CAPACITY: usize = 256;
+/// Upper bound of search nodes visited per query, so a single unreachable goal can't stall a frame.
+pub const MAX_EXPANSIONS: usize = 50_000;
+/// An agent further than this from its route counts as off-route and gets replanned.
+pub const OFF_ROUTE_DISTANCE: f32 = TILE_TEX_SIZE_SCALED * 0.75;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RouteKind {
+    /// Follows lanes in their driving direction and only takes permitted turns.
+    Vehicle,
+    /// Walks over sidewalks and only steps onto the road at crossings.
+    Pedestrian,
+}
+
+#[derive(Debug, Clone)]
+pub struct Route {
+    pub kind: RouteKind,
+    pub waypoints: Vec<Vec2>,
+    /// Lane graph edges the route drives along, empty for pedestrian routes.
+    pub edges: Vec<EdgeId>,
+    pub goal: Vec2,
+    goal_lane: Option<LanePoint>,
+}
+
+impl Route {
+    pub fn length(&self) -> f32 {
+        self.waypoints.windows(2).map(|w| w[0].distance(w[1])).sum()
+    }
+
+    /// Index of the waypoint segment closest to `pos`. Segment `i` runs from waypoint `i` to `i + 1`.
+    pub fn nearest_segment(&self, pos: Vec2) -> usize {
+        (0..self.waypoints.len().saturating_sub(1))
+            .min_by(|a, b| {
+                self.segment_distance(*a, pos)
+                    .total_cmp(&self.segment_distance(*b, pos))
+            })
+            .unwrap_or(0)
+    }
+
+    /// Shortest distance from `pos` to the route.
+    pub fn distance_to(&self, pos: Vec2) -> f32 {
+        if self.waypoints.len() < 2 {
+            return self.waypoints.first().map_or(f32::INFINITY, |w| w.distance(pos));
+        }
+        self.segment_distance(self.nearest_segment(pos), pos)
+    }
+
+    /// The point `lookahead` units further along the route than the point closest to `pos`.
+    pub fn point_ahead(&self, pos: Vec2, lookahead: f32) -> Vec2 {
+        let Some(last) = self.waypoints.last().copied() else {
+            return pos;
+        };
+        if self.waypoints.len() < 2 {
+            return last;
+        }
+        let segment = self.nearest_segment(pos);
+        let (a, b) = (self.waypoints[segment], self.waypoints[segment + 1]);
+        let ab = b - a;
+        let t = if ab.length_squared() > 0. {
+            ((pos - a).dot(ab) / ab.length_squared()).clamp(0., 1.)
+        } else {
+            0.
+        };
+        let mut remaining = lookahead + ab.length() * t;
+        for w in self.waypoints[segment..].windows(2) {
+            let length = w[0].distance(w[1]);
+            if remaining <= length {
+                return w[0] + (w[1] - w[0]).normalize_or_zero() * remaining;
+            }
+            remaining -= length;
+        }
+        last
+    }
+
+    pub fn is_off_route(&self, pos: Vec2) -> bool {
+        self.distance_to(pos) > OFF_ROUTE_DISTANCE
+    }
+
+    fn segment_distance(&self, segment: usize, pos: Vec2) -> f32 {
+        let (a, b) = (self.waypoints[segment], self.waypoints[segment + 1]);
+        let ab = b - a;
+        let t = if ab.length_squared() > 0. {
+            ((pos - a).dot(ab) / ab.length_squared()).clamp(0., 1.)
+        } else {
+            0.
+        };
+        (a + ab * t).distance(pos)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct RouteKey {
+    kind: RouteKind,
+    start_tile: (usize, usize),
+    start_edge: Option<EdgeId>,
+    goal_tile: (usize, usize),
+}
+
+/// Route planning shared by every agent in the world: AI drivers, pedestrians, police and the GPS.
+pub struct Navigation {
+    walkable: Array2<bool>,
+    cache: HashMap<RouteKey, Rc<Route>>,
+    cache_order: VecDeque<RouteKey>,
+}
+
+impl Navigation {
+    pub fn new(map: &Map) -> Self {
+        let size = map.world_size();
+        let mut walkable = Array2::from_elem((size, size), false);
+        for (index, tile) in map.indexed_tiles() {
+            let variant = tile.variant();
+            walkable[index] = !variant.is_road() || variant.is_junction();
+        }
+        Navigation {
+            walkable,
+            cache: HashMap::new(),
+            cache_order: VecDeque::new(),
+        }
+    }
+
+    pub fn is_walkable(&self, tile: (usize, usize)) -> bool {
+        self.walkable.get(tile).copied().unwrap_or(false)
+    }
+
+    pub fn clear_cache(&mut self) {
+        self.cache.clear();
+        self.cache_order.clear();
+    }
+
+    /// Plans a driving route from `from` to the lane closest to `to`.
+    /// `heading` is the direction the vehicle is facing and decides which lane it starts on.
+    /// Either end off the road is moved to the nearest road tile.
+    pub fn vehicle_route(
+        &mut self,
+        roads: &RoadGraph,
+        map: &Map,
+        from: Vec2,
+        heading: Option<Vec2>,
+        to: Vec2,
+    ) -> Option<Rc<Route>> {
+        let start = roads.snap_to_lane(map, from, heading)?;
+        let key = RouteKey {
+            kind: RouteKind::Vehicle,
+            start_tile: map.to_tile_index_pos(from),
+            start_edge: Some(start.edge),
+            goal_tile: map.to_tile_index_pos(to),
+        };
+        if let Some(route) = self.cache.get(&key) {
+            // Routes are cached by tile, so the exact start and goal may differ from the cached ones
+            if route.goal == to && route.waypoints.first() == Some(&start.pos) {
+                return Some(route.clone());
+            }
+            let goal_lane = roads.nearest_lane_on(*route.edges.last()?, to)?;
+            // Unless the goal is now behind the start on the same lane, which needs another way around
+            if route.edges.len() > 1 || goal_lane.distance >= start.distance {
+                return Some(Rc::new(build_vehicle_route(roads, &start, route.edges.clone(), goal_lane, to)));
+            }
+        }
+
+        // Off-road destinations are reached from the nearest road.
+        let goal_tile = roads.nearest_road_tile(map, to)?;
+        let goals: HashMap<EdgeId, f32> = roads
+            .edges_at(map, map.tile_center(goal_tile))
+            .iter()
+            .filter_map(|edge| roads.nearest_lane_on(*edge, to))
+            .map(|point| (point.edge, point.distance))
+            .collect();
+        let edges = search_lanes(roads, &start, &goals, Some(to))?;
+        let last = *edges.last().unwrap();
+        let goal_lane = roads.nearest_lane_on(last, to)?;
+        let route = Rc::new(build_vehicle_route(roads, &start, edges, goal_lane, to));
+        self.insert(key, route.clone());
+        Some(route)
+    }
+
+    /// Plans a walking route from `from` to `to` over sidewalks and crossings.
+    pub fn pedestrian_route(&mut self, map: &Map, from: Vec2, to: Vec2) -> Option<Rc<Route>> {
+        let start = map.to_tile_index_pos(from);
+        let goal = map.to_tile_index_pos(to);
+        let key = RouteKey {
+            kind: RouteKind::Pedestrian,
+            start_tile: start,
+            start_edge: None,
+            goal_tile: goal,
+        };
+        if let Some(route) = self.cache.get(&key) {
+            // Routes are cached by tile, the ends are put where they were asked for
+            let mut route = Route::clone(route);
+            if let [first, .., last] = route.waypoints.as_mut_slice() {
+                (*first, *last) = (from, to);
+            }
+            route.goal = to;
+            return Some(Rc::new(route));
+        }
+
+        let tiles = self.search_tiles(start, goal)?;
+        let mut waypoints = vec![from];
+        waypoints.extend(
+            tiles
+                .iter()
+                .skip(1)
+                .take(tiles.len().saturating_sub(2))
+                .map(|tile| map.tile_center(*tile)),
+        );
+        waypoints.push(to);
+        let route = Rc::new(Route {
+            kind: RouteKind::Pedestrian,
+            waypoints,
+            edges: Vec::new(),
+            goal: to,
+            goal_lane: None,
+        });
+        self.insert(key, route.clone());
+        Some(route)
+    }
+
+    /// Returns a new route if `pos` has strayed from `route`, or `None` while it is still being followed.
+    /// Vehicle routes are repaired by finding the shortest way back onto the remaining route,
+    /// only falling back to planning from scratch when that fails.
+    pub fn replan(
+        &mut self,
+        roads: &RoadGraph,
+        map: &Map,
+        route: &Route,
+        pos: Vec2,
+        heading: Option<Vec2>,
+    ) -> Option<Rc<Route>> {
+        if !route.is_off_route(pos) {
+            return None;
+        }
+        match route.kind {
+            RouteKind::Pedestrian => self.pedestrian_route(map, pos, route.goal),
+            RouteKind::Vehicle => self
+                .rejoin(roads, map, route, pos, heading)
+                .map(Rc::new)
+                .or_else(|| self.vehicle_route(roads, map, pos, heading, route.goal)),
+        }
+    }
+
+    fn rejoin(
+        &self,
+        roads: &RoadGraph,
+        map: &Map,
+        route: &Route,
+        pos: Vec2,
+        heading: Option<Vec2>,
+    ) -> Option<Route> {
+        let start = roads.nearest_lane(map, pos, heading)?;
+        let goal_lane = route.goal_lane?;
+        if let Some(index) = route.edges.iter().position(|e| *e == start.edge) {
+            let edges = route.edges[index..].to_vec();
+            return Some(build_vehicle_route(roads, &start, edges, goal_lane, route.goal));
+        }
+        let goals: HashMap<EdgeId, f32> = route.edges.iter().map(|e| (*e, 0.)).collect();
+        let mut edges = search_lanes(roads, &start, &goals, None)?;
+        let rejoined = edges.pop()?;
+        let index = route.edges.iter().position(|e| *e == rejoined)?;
+        edges.extend_from_slice(&route.edges[index..]);
+        Some(build_vehicle_route(roads, &start, edges, goal_lane, route.goal))
+    }
+
+    fn insert(&mut self, key: RouteKey, route: Rc<Route>) {
+        if self.cache.insert(key, route).is_none() {
+            self.cache_order.push_back(key);
+        }
+        while self.cache_order.len() > ROUTE_CACHE_CAPACITY {
+            if let Some(oldest) = self.cache_order.pop_front() {
+                self.cache.remove(&oldest);
+            }
+        }
+    }
+
+    /// A* over the tile grid. Diagonal steps are what gets pedestrians from a block's
+    /// corner onto the crossing next to it. The start and goal tiles are always allowed so agents standing on the road can still leave it.
+    fn search_tiles(&self, start: (usize, usize), goal: (usize, usize)) -> Option<Vec<(usize, usize)>> {
+        let passable = |tile: (usize, usize)| tile == goal || self.is_walkable(tile);
+        let heuristic = |tile: (usize, usize)| {
+            Vec2::new(tile.0 as f32, tile.1 as f32).distance(Vec2::new(goal.0 as f32, goal.1 as f32))
+        };
+        let mut open = BinaryHeap::new();
+        let mut came_from: HashMap<(usize, usize), (usize, usize)> = HashMap::new();
+        let mut cost: HashMap<(usize, usize), f32> = HashMap::from([(start, 0.)]);
+        open.push(Open { f: heuristic(start), g: 0., state: start });
+
+        let mut expansions = 0;
+        while let Some(Open { g, state: tile, .. }) = open.pop() {
+            if tile == goal {
+                let mut path = vec![tile];
+                while let Some(previous) = came_from.get(path.last().unwrap()) {
+                    path.push(*previous);
+                }
+                path.reverse();
+                return Some(path);
+            }
+            if g > cost[&tile] {
+                continue;
+            }
+            expansions += 1;
+            if expansions > MAX_EXPANSIONS {
+                return None;
+            }
+            for (dx, dy) in [(0, -1), (1, 0), (0, 1), (-1, 0), (1, -1), (1, 1), (-1, 1), (-1, -1)] {
+                let Some(next) = offset(tile, dx, dy) else {
+                    continue;
+                };
+                if !passable(next) {
+                    continue;
+                }
+                let next_cost = g + if dx != 0 && dy != 0 { std::f32::consts::SQRT_2 } else { 1. };
+                if cost.get(&next).is_none_or(|c| next_cost < *c) {
+                    cost.insert(next, next_cost);
+                    came_from.insert(next, tile);
+                    open.push(Open { f: next_cost + heuristic(next), g: next_cost, state: next });
+                }
+            }
+        }
+        None
+    }
+}
+
+fn offset(tile: (usize, usize), dx: isize, dy: isize) -> Option<(usize, usize)> {
+    Some((tile.0.checked_add_signed(dx)?, tile.1.checked_add_signed(dy)?))
+}
+
+/// A* over lane graph edges. The cost of a state is the distance driven up to the start of the edge,
+/// so the start edge begins at minus the distance already covered on it.
+/// `goals` maps edges to the distance along them at which the search may stop.
+/// Returns the edges from the start edge up to and including the goal edge.
+fn search_lanes(
+    roads: &RoadGraph,
+    start: &LanePoint,
+    goals: &HashMap<EdgeId, f32>,
+    target: Option<Vec2>,
+) -> Option<Vec<EdgeId>> {
+    /// Coming back around onto the start edge is a visit of its own, `lap` tells it from the first one.
+    /// That way a goal behind the start can be reached by going around the block.
+    #[derive(Clone, Copy, PartialEq, Eq, Hash)]
+    struct Visit {
+        edge: EdgeId,
+        lap: bool,
+    }
+    #[derive(Clone, Copy)]
+    enum State {
+        Edge(Visit),
+        Goal(Visit),
+    }
+    let heuristic = |edge: EdgeId| target.map_or(0., |t| roads.edge(edge).path[0].distance(t));
+
+    let first = Visit { edge: start.edge, lap: false };
+    let mut open = BinaryHeap::new();
+    let mut came_from: HashMap<Visit, Visit> = HashMap::new();
+    let mut cost: HashMap<Visit, f32> = HashMap::from([(first, -start.distance)]);
+    open.push(Open { f: heuristic(start.edge), g: -start.distance, state: State::Edge(first) });
+
+    let mut expansions = 0;
+    while let Some(Open { g, state, .. }) = open.pop() {
+        let visit = match state {
+            State::Goal(visit) => {
+                let mut path = vec![visit];
+                while let Some(previous) = came_from.get(path.last().unwrap()) {
+                    path.push(*previous);
+                }
+                return Some(path.iter().rev().map(|visit| visit.edge).collect());
+            }
+            State::Edge(visit) => visit,
+        };
+        if g > cost[&visit] {
+            continue;
+        }
+        expansions += 1;
+        if expansions > MAX_EXPANSIONS {
+            return None;
+        }
+        if let Some(distance) = goals.get(&visit.edge) {
+            // On the first visit of the start edge the goal has to lie ahead of us
+            if g + distance >= 0. {
+                open.push(Open { f: g + distance, g: g + distance, state: State::Goal(visit) });
+            }
+        }
+        let end_cost = g + roads.edge(visit.edge).length;
+        for turn in &roads.edge(visit.edge).turns {
+            let next = Visit {
+                edge: turn.to,
+                lap: turn.to == start.edge,
+            };
+            if cost.get(&next).is_none_or(|c| end_cost < *c) {
+                cost.insert(next, end_cost);
+                came_from.insert(next, visit);
+                open.push(Open { f: end_cost + heuristic(turn.to), g: end_cost, state: State::Edge(next) });
+            }
+        }
+    }
+    None
+}
+
+fn build_vehicle_route(
+    roads: &RoadGraph,
+    start: &LanePoint,
+    edges: Vec<EdgeId>,
+    goal_lane: LanePoint,
+    goal: Vec2,
+) -> Route {
+    let mut waypoints = vec![start.pos];
+    let last = edges.len() - 1;
+    for (i, id) in edges.iter().enumerate() {
+        let edge = roads.edge(*id);
+        let from = if i == 0 { start.distance } else { f32::NEG_INFINITY };
+        let to = if i == last { goal_lane.distance } else { f32::INFINITY };
+        let mut walked = 0.;
+        for (j, point) in edge.path.iter().enumerate() {
+            if j > 0 {
+                walked += edge.path[j - 1].distance(*point);
+            }
+            if walked > from && walked < to {
+                waypoints.push(*point);
+            }
+        }
+    }
+    waypoints.push(goal_lane.pos);
+    Route {
+        kind: RouteKind::Vehicle,
+        waypoints,
+        edges,
+        goal,
+        goal_lane: Some(goal_lane),
+    }
+}
+
+/// Entry of the open set, ordered so that `BinaryHeap` pops the lowest `f` first.
+struct Open<S> {
+    f: f32,
+    g: f32,
+    state: S,
+}
+
+impl<S> PartialEq for Open<S> {
+    fn eq(&self, other: &Self) -> bool {
+        self.f == other.f
+    }
+}
+
+impl<S> Eq for Open<S> {}
+
+impl<S> PartialOrd for Open<S> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<S> Ord for Open<S> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.f.total_cmp(&self.f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use macroquad::miniquad::{RawId, TextureId};
+
+    use super::*;
+
+    /// A 16x16 tile city with a road every 4 tiles, so the blocks are 2048 units apart.
+    fn city() -> (Map, RoadGraph) {
+        let texture = Texture2D::from_miniquad_texture(TextureId::from_raw_id(RawId::OpenGl(0)));
+        let map = Map::generate_city(texture, 16, 4);
+        let roads = RoadGraph::from_map(&map);
+        (map, roads)
+    }
+
+    /// Halfway between two intersections, driving east.
+    fn start(map: &Map, roads: &RoadGraph) -> LanePoint {
+        roads.nearest_lane(map, Vec2::new(1024., 0.), Some(Vec2::X)).unwrap()
+    }
+
+    #[test]
+    fn goal_ahead_on_the_start_edge() {
+        let (map, roads) = city();
+        let start = start(&map, &roads);
+        let goals = HashMap::from([(start.edge, start.distance + 200.)]);
+        assert_eq!(search_lanes(&roads, &start, &goals, None), Some(vec![start.edge]));
+    }
+
+    #[test]
+    fn goal_behind_on_the_start_edge_goes_around_the_block() {
+        let (map, roads) = city();
+        let start = start(&map, &roads);
+        let goals = HashMap::from([(start.edge, start.distance - 200.)]);
+        let edges = search_lanes(&roads, &start, &goals, None).unwrap();
+        assert!(edges.len() > 2, "{edges:?}");
+        assert_eq!(edges.first(), Some(&start.edge));
+        assert_eq!(edges.last(), Some(&start.edge));
+    }
+
+    #[test]
+    fn start_edge_is_no_goal_without_going_around() {
+        let (map, roads) = city();
+        let start = start(&map, &roads);
+        let goals = HashMap::from([(start.edge, 0.)]);
+        let edges = search_lanes(&roads, &start, &goals, None).unwrap();
+        assert!(edges.len() > 1, "{edges:?}");
+    }
+
+    #[test]
+    fn unreachable_goal_gives_no_route() {
+        let (map, roads) = city();
+        let start = start(&map, &roads);
+        assert_eq!(search_lanes(&roads, &start, &HashMap::new(), None), None);
+    }
+
+    #[test]
+    fn cached_vehicle_routes_end_at_the_exact_goal() {
+        let (map, roads) = city();
+        let mut navigation = Navigation::new(&map);
+        let from = Vec2::new(1024., 0.);
+        // Both on the same tile
+        let first = Vec2::new(-2048., 900.);
+        let second = Vec2::new(-2048., 1100.);
+        let a = navigation.vehicle_route(&roads, &map, from, Some(Vec2::X), first).unwrap();
+        let b = navigation.vehicle_route(&roads, &map, from, Some(Vec2::X), second).unwrap();
+        assert_eq!(a.goal, first);
+        assert_eq!(b.goal, second);
+        assert_eq!(a.edges, b.edges);
+        assert!(a.waypoints.last().unwrap().distance(first) < TILE_TEX_SIZE_SCALED / 2.);
+        assert!(b.waypoints.last().unwrap().distance(second) < TILE_TEX_SIZE_SCALED / 2.);
+        assert_ne!(a.waypoints.last(), b.waypoints.last());
+    }
+
+    #[test]
+    fn cached_pedestrian_routes_end_at_the_exact_goal() {
+        let (map, _) = city();
+        let mut navigation = Navigation::new(&map);
+        let from = Vec2::new(256., 256.);
+        let a = navigation.pedestrian_route(&map, from, Vec2::new(3000., 3000.)).unwrap();
+        let b = navigation.pedestrian_route(&map, from, Vec2::new(3050., 3050.)).unwrap();
+        assert_eq!(a.waypoints.last(), Some(&Vec2::new(3000., 3000.)));
+        assert_eq!(b.waypoints.last(), Some(&Vec2::new(3050., 3050.)));
+        assert_eq!(b.goal, Vec2::new(3050., 3050.));
+    }
+}
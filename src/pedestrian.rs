@@ -0,0 +1,501 @@
+use std::{cell::RefCell, rc::Rc};
+
+use macroquad::prelude::*;
+
+use crate::{
+    animation::{self, Animation, SpriteSheet},
+    draw::Draw,
+    intersection::Intersections,
+    map::{Map, TILE_TEX_SIZE_SCALED},
+    navigation::{Navigation, Route},
+    player::{sprite_x_offset, PLAYER_SPRITE_SIZE, SCALED_PLAYER_SPRITE_SIZE},
+    rng::Rng,
+    vehicle::Vehicle,
+    Direction,
+};
+
+pub const SPAWN_RADIUS_MIN: f32 = 900.;
+pub const SPAWN_RADIUS_MAX: f32 = 1800.;
+pub const DESPAWN_RADIUS: f32 = 2500.;
+pub const MAX_PEDESTRIANS: usize = 20;
+const SPAWN_ATTEMPTS_PER_FRAME: usize = 4;
+
+pub const WALK_SPEED: f32 = 120.;
+pub const RUN_SPEED: f32 = 320.;
+/// Wandering pedestrians pick their next destination at most this many tiles away.
+const WANDER_RADIUS_TILES: isize = 6;
+const WAYPOINT_REACHED_DISTANCE: f32 = 16.;
+
+/// Pedestrians closer than this push each other apart.
+const PERSONAL_SPACE: f32 = 48.;
+const SEPARATION_STRENGTH: f32 = 2.;
+/// Panic spreads to pedestrians within this distance of someone fleeing.
+const PANIC_RADIUS: f32 = 200.;
+pub const GUNSHOT_HEARING_RADIUS: f32 = 1200.;
+const FLEE_DURATION: f32 = 5.;
+
+/// Vehicles faster than this heading towards a pedestrian make them jump aside.
+const DANGEROUS_SPEED: f32 = 4.;
+const DANGER_DISTANCE: f32 = 450.;
+/// Distance between a vehicle's and a pedestrian's center at which they collide.
+const HIT_DISTANCE: f32 = 90.;
+const KNOCKDOWN_SPEED: f32 = 1.;
+const KNOCKDOWN_DURATION: f32 = 3.;
+pub const MAX_HEALTH: f32 = 100.;
+/// Damage taken per unit of vehicle speed when being run over.
+const HIT_DAMAGE_PER_SPEED: f32 = 8.;
+
+/// Chance of a driver pulled out of their vehicle fighting back instead of running.
+const FIGHT_BACK_CHANCE: f32 = 0.4;
+const ATTACK_DURATION: f32 = 8.;
+const PUNCH_RANGE: f32 = 50.;
+const PUNCH_COOLDOWN: f32 = 0.8;
+pub const PUNCH_DAMAGE: f32 = 5.;
+/// Shots passing closer than this to a pedestrian's center hit them.
+const SHOT_HIT_RADIUS: f32 = 32.;
+const SHOT_RANGE: f32 = 1500.;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PedestrianState {
+    Wandering,
+    Fleeing { from: Vec2, time_left: f32 },
+    /// Going after the player with bare fists.
+    Attacking { time_left: f32, cooldown: f32 },
+    KnockedDown { time_left: f32 },
+    Dead,
+}
+
+pub struct Pedestrian {
+    /// Tells pedestrians in the crowd apart, assigned by `Crowd::add`.
+    pub id: u32,
+    pub pos: Vec2,
+    pub facing: Direction,
+    pub texture: Texture2D,
+    pub state: PedestrianState,
+    pub health: f32,
+    pub tint: Color,
+    /// Needed by a mission, so never despawned.
+    pub mission_target: bool,
+    pub animation: Animation,
+    route: Option<Rc<Route>>,
+    waypoint: usize,
+    /// How fast the pedestrian walked since the animation was last updated.
+    speed: f32,
+}
+
+impl Pedestrian {
+    pub fn new(sprite: SpriteSheet, pos: Vec2) -> Self {
+        Pedestrian {
+            id: 0,
+            pos,
+            facing: Direction::South,
+            texture: sprite.texture,
+            state: PedestrianState::Wandering,
+            health: MAX_HEALTH,
+            tint: WHITE,
+            mission_target: false,
+            animation: Animation::new(sprite.animations),
+            route: None,
+            waypoint: 0,
+            speed: 0.,
+        }
+    }
+
+    pub fn is_down(&self) -> bool {
+        matches!(
+            self.state,
+            PedestrianState::KnockedDown { .. } | PedestrianState::Dead
+        )
+    }
+
+    pub fn flee(&mut self, from: Vec2) {
+        if !self.is_down() {
+            self.state = PedestrianState::Fleeing {
+                from,
+                time_left: FLEE_DURATION,
+            };
+            self.route = None;
+        }
+    }
+
+    /// Gets hit by a vehicle going at `speed`. Returns the damage taken if the pedestrian was knocked down.
+    pub fn hit(&mut self, speed: f32) -> Option<f32> {
+        if self.is_down() || speed < KNOCKDOWN_SPEED {
+            return None;
+        }
+        let damage = speed * HIT_DAMAGE_PER_SPEED;
+        self.health -= damage;
+        self.route = None;
+        self.state = if self.health <= 0. {
+            PedestrianState::Dead
+        } else {
+            PedestrianState::KnockedDown {
+                time_left: KNOCKDOWN_DURATION,
+            }
+        };
+        Some(damage)
+    }
+
+    /// Gets shot by someone standing at `from`.
+    pub fn shot(&mut self, damage: f32, from: Vec2) {
+        self.health -= damage;
+        if self.health <= 0. {
+            self.state = PedestrianState::Dead;
+            self.route = None;
+        } else {
+            self.flee(from);
+        }
+    }
+
+    /// Returns the damage dealt to the player.
+    #[allow(clippy::too_many_arguments)]
+    fn update(
+        &mut self,
+        navigation: &mut Navigation,
+        map: &Map,
+        intersections: &Intersections,
+        separation: Vec2,
+        player_on_foot: Option<Vec2>,
+        rng: &mut Rng,
+        dt: f32,
+    ) -> f32 {
+        match self.state {
+            PedestrianState::Dead => {}
+            PedestrianState::Attacking { time_left, cooldown } => {
+                let Some(player_pos) = player_on_foot.filter(|_| time_left > dt) else {
+                    // The player got away, one way or another
+                    self.state = PedestrianState::Wandering;
+                    return 0.;
+                };
+                let to_player = player_pos - self.pos;
+                let cooldown = (cooldown - dt).max(0.);
+                self.state = PedestrianState::Attacking {
+                    time_left: time_left - dt,
+                    cooldown,
+                };
+                if to_player.length() > PUNCH_RANGE {
+                    self.walk((to_player.normalize() + separation).normalize_or_zero(), RUN_SPEED, dt);
+                } else if cooldown == 0. {
+                    self.facing = Direction::from_vector(to_player);
+                    self.state = PedestrianState::Attacking {
+                        time_left: time_left - dt,
+                        cooldown: PUNCH_COOLDOWN,
+                    };
+                    return PUNCH_DAMAGE;
+                }
+            }
+            PedestrianState::KnockedDown { time_left } => {
+                self.state = if time_left > dt {
+                    PedestrianState::KnockedDown {
+                        time_left: time_left - dt,
+                    }
+                } else {
+                    PedestrianState::Fleeing {
+                        from: self.pos,
+                        time_left: FLEE_DURATION,
+                    }
+                };
+            }
+            PedestrianState::Fleeing { from, time_left } => {
+                let away = (self.pos - from).normalize_or(Vec2::Y);
+                self.walk((away + separation).normalize_or_zero(), RUN_SPEED, dt);
+                self.state = if time_left > dt {
+                    PedestrianState::Fleeing {
+                        from,
+                        time_left: time_left - dt,
+                    }
+                } else {
+                    PedestrianState::Wandering
+                };
+            }
+            PedestrianState::Wandering => {
+                let route_finished = self
+                    .route
+                    .as_ref()
+                    .is_none_or(|route| self.waypoint >= route.waypoints.len());
+                if route_finished {
+                    self.route = wander_goal(navigation, map, self.pos, rng)
+                        .and_then(|goal| navigation.pedestrian_route(map, self.pos, goal));
+                    self.waypoint = 0;
+                }
+                let Some(target) = self
+                    .route
+                    .as_ref()
+                    .and_then(|route| route.waypoints.get(self.waypoint).copied())
+                else {
+                    return 0.;
+                };
+                if self.pos.distance(target) < WAYPOINT_REACHED_DISTANCE {
+                    self.waypoint += 1;
+                    return 0.;
+                }
+                let next_tile = map.to_tile_index_pos(target);
+                if next_tile != map.to_tile_index_pos(self.pos) && !intersections.may_cross(next_tile) {
+                    // Wait for the lights
+                    return 0.;
+                }
+                let towards = (target - self.pos).normalize_or_zero();
+                self.walk((towards + separation).normalize_or_zero(), WALK_SPEED, dt);
+            }
+        }
+        0.
+    }
+
+    pub fn walk(&mut self, direction: Vec2, speed: f32, dt: f32) {
+        if direction == Vec2::ZERO {
+            return;
+        }
+        self.facing = Direction::from_vector(direction);
+        self.pos += direction * speed * dt;
+        self.speed = speed;
+    }
+
+    /// Shows the clip for what the pedestrian is doing and moves it along.
+    pub fn animate(&mut self, dt: f32) {
+        let clip = match self.state {
+            PedestrianState::Dead | PedestrianState::KnockedDown { .. } => animation::DIE,
+            _ if self.speed > WALK_SPEED => animation::RUN,
+            _ if self.speed > 0. => animation::WALK,
+            _ => animation::IDLE,
+        };
+        self.animation.show(clip);
+        self.animation.update(dt);
+        self.speed = 0.;
+    }
+}
+
+/// The first of `pedestrians` still standing in the line of a shot, and how far away along it they are.
+pub fn first_in_line<'a>(
+    pedestrians: impl Iterator<Item = &'a mut Pedestrian>,
+    from: Vec2,
+    direction: Vec2,
+) -> Option<(&'a mut Pedestrian, f32)> {
+    let direction = direction.normalize_or_zero();
+    pedestrians
+        .filter(|p| !p.is_down())
+        .filter_map(|p| {
+            let along = (p.pos - from).dot(direction);
+            let off_line = (p.pos - from).perp_dot(direction).abs();
+            (along > 0. && along < SHOT_RANGE && off_line < SHOT_HIT_RADIUS).then_some((p, along))
+        })
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+}
+
+/// A random walkable spot a few tiles away from `pos`.
+fn wander_goal(navigation: &Navigation, map: &Map, pos: Vec2, rng: &mut Rng) -> Option<Vec2> {
+    let (x, y) = map.to_tile_index_pos(pos);
+    (0..8).find_map(|_| {
+        let tile = (
+            x.checked_add_signed(rng.gen_range(-WANDER_RADIUS_TILES, WANDER_RADIUS_TILES + 1))?,
+            y.checked_add_signed(rng.gen_range(-WANDER_RADIUS_TILES, WANDER_RADIUS_TILES + 1))?,
+        );
+        navigation
+            .is_walkable(tile)
+            .then(|| map.tile_center(tile) + random_offset(TILE_TEX_SIZE_SCALED / 3., rng))
+    })
+}
+
+fn random_offset(max: f32, rng: &mut Rng) -> Vec2 {
+    Vec2::new(rng.gen_range(-max, max), rng.gen_range(-max, max))
+}
+
+impl Draw for Pedestrian {
+    fn texture(&self) -> &Texture2D {
+        &self.texture
+    }
+
+    fn texture_size() -> f32 {
+        PLAYER_SPRITE_SIZE
+    }
+
+    fn texture_size_scaled() -> f32 {
+        SCALED_PLAYER_SPRITE_SIZE
+    }
+
+    fn draw_at_screen_space(&self, screen_pos: Vec2) {
+        draw_texture_ex(
+            &self.texture,
+            screen_pos.x,
+            screen_pos.y,
+            if self.state == PedestrianState::Dead { GRAY } else { self.tint },
+            DrawTextureParams {
+                dest_size: Some((SCALED_PLAYER_SPRITE_SIZE, SCALED_PLAYER_SPRITE_SIZE).into()),
+                source: Some(Rect {
+                    x: sprite_x_offset(self.facing),
+                    y: self.animation.row() as f32 * PLAYER_SPRITE_SIZE,
+                    w: PLAYER_SPRITE_SIZE,
+                    h: PLAYER_SPRITE_SIZE,
+                }),
+                ..Default::default()
+            },
+        );
+    }
+
+    fn position(&self) -> Vec2 {
+        self.pos
+    }
+}
+
+/// A pedestrian run over by a vehicle.
+pub struct Knockdown {
+    pub vehicle: Rc<RefCell<Vehicle>>,
+    pub pedestrian: u32,
+    pub damage: f32,
+}
+
+/// What the crowd did during one update.
+#[derive(Default)]
+pub struct CrowdEvents {
+    pub knockdowns: Vec<Knockdown>,
+    pub damage_to_player: f32,
+}
+
+/// All pedestrians walking around the player.
+#[derive(Default)]
+pub struct Crowd {
+    pub pedestrians: Vec<Pedestrian>,
+    /// Sprites randomly picked from for newly spawned pedestrians. Nothing spawns while this is empty.
+    pub sprites: Vec<SpriteSheet>,
+    next_id: u32,
+}
+
+impl Crowd {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds `pedestrian` to the crowd with a new id.
+    pub fn add(&mut self, mut pedestrian: Pedestrian) {
+        self.next_id += 1;
+        pedestrian.id = self.next_id;
+        self.pedestrians.push(pedestrian);
+    }
+
+    /// Everyone in earshot of a shot fired at `pos` runs away from it.
+    pub fn alert_gunshot(&mut self, pos: Vec2) {
+        for pedestrian in &mut self.pedestrians {
+            if pedestrian.pos.distance(pos) < GUNSHOT_HEARING_RADIUS {
+                pedestrian.flee(pos);
+            }
+        }
+    }
+
+    /// A driver pulled out of their vehicle at `pos`, who either flees from or fights `attacker_pos`.
+    pub fn add_ejected_driver(&mut self, pos: Vec2, attacker_pos: Vec2, rng: &mut Rng) {
+        let Some(sprite) = rng.choose(&self.sprites).cloned() else {
+            return;
+        };
+        let mut driver = Pedestrian::new(sprite, pos);
+        if rng.chance(FIGHT_BACK_CHANCE) {
+            driver.state = PedestrianState::Attacking {
+                time_left: ATTACK_DURATION,
+                cooldown: PUNCH_COOLDOWN,
+            };
+        } else {
+            driver.flee(attacker_pos);
+        }
+        self.add(driver);
+    }
+
+    /// Positions of pedestrians still standing, for drivers to brake for.
+    pub fn obstacles(&self) -> impl Iterator<Item = Vec2> + '_ {
+        self.pedestrians.iter().filter(|p| !p.is_down()).map(|p| p.pos)
+    }
+
+    pub fn despawn_far_away(&mut self, player_pos: Vec2) {
+        self.pedestrians
+            .retain(|p| p.mission_target || p.pos.distance(player_pos) <= DESPAWN_RADIUS);
+    }
+
+
+    pub fn spawn(&mut self, navigation: &Navigation, map: &Map, player_pos: Vec2, rng: &mut Rng) {
+        for _ in 0..SPAWN_ATTEMPTS_PER_FRAME {
+            if self.pedestrians.len() >= MAX_PEDESTRIANS {
+                return;
+            }
+            let Some(sprite) = rng.choose(&self.sprites).cloned() else {
+                return;
+            };
+            let angle = rng.gen_range(0., core::f32::consts::TAU);
+            let radius = rng.gen_range(SPAWN_RADIUS_MIN, SPAWN_RADIUS_MAX);
+            let candidate = player_pos + Vec2::from_angle(angle) * radius;
+            if navigation.is_walkable(map.to_tile_index_pos(candidate)) {
+                self.add(Pedestrian::new(sprite, candidate));
+            }
+        }
+    }
+
+    /// Moves every pedestrian and resolves collisions with vehicles.
+    #[allow(clippy::too_many_arguments)]
+    pub fn update(
+        &mut self,
+        navigation: &mut Navigation,
+        map: &Map,
+        intersections: &Intersections,
+        vehicles: &[Rc<RefCell<Vehicle>>],
+        player_on_foot: Option<Vec2>,
+        rng: &mut Rng,
+        dt: f32,
+    ) -> CrowdEvents {
+        let mut events = CrowdEvents::default();
+        for vehicle in vehicles {
+            let vehicle_ref = vehicle.borrow();
+            let speed = vehicle_ref.velocity;
+            let vehicle_pos = vehicle_ref.position();
+            let heading = vehicle_ref.rotation * if vehicle_ref.reversed { -1. } else { 1. };
+            for pedestrian in &mut self.pedestrians {
+                let distance = pedestrian.pos.distance(vehicle_pos);
+                if distance < HIT_DISTANCE {
+                    if let Some(damage) = pedestrian.hit(speed) {
+                        events.knockdowns.push(Knockdown {
+                            vehicle: vehicle.clone(),
+                            pedestrian: pedestrian.id,
+                            damage,
+                        });
+                    }
+                } else if speed > DANGEROUS_SPEED
+                    && distance < DANGER_DISTANCE
+                    && heading.dot(pedestrian.pos - vehicle_pos) > 0.
+                {
+                    pedestrian.flee(vehicle_pos);
+                }
+            }
+        }
+
+        let panic_sources: Vec<(Vec2, Vec2)> = self
+            .pedestrians
+            .iter()
+            .filter_map(|p| match p.state {
+                PedestrianState::Fleeing { from, .. } => Some((p.pos, from)),
+                _ => None,
+            })
+            .collect();
+        let positions: Vec<Vec2> = self.pedestrians.iter().map(|p| p.pos).collect();
+        for (i, pedestrian) in self.pedestrians.iter_mut().enumerate() {
+            if pedestrian.state == PedestrianState::Wandering {
+                if let Some((_, from)) = panic_sources
+                    .iter()
+                    .find(|(pos, _)| pos.distance(pedestrian.pos) < PANIC_RADIUS)
+                {
+                    pedestrian.flee(*from);
+                }
+            }
+            let separation = positions
+                .iter()
+                .enumerate()
+                .filter(|(j, other)| *j != i && other.distance(pedestrian.pos) < PERSONAL_SPACE)
+                .map(|(_, other)| (pedestrian.pos - *other).normalize_or_zero())
+                .sum::<Vec2>()
+                * SEPARATION_STRENGTH;
+            events.damage_to_player += pedestrian.update(navigation, map, intersections, separation, player_on_foot, rng, dt);
+            pedestrian.animate(dt);
+        }
+        events
+    }
+
+    pub fn draw(&self, camera_pos: Vec2) {
+        for pedestrian in &self.pedestrians {
+            pedestrian.draw_at_world_space(camera_pos);
+        }
+    }
+}
@@ -1,32 +1,116 @@
 use std::{cell::RefCell, rc::Rc};
 
-use crate::{draw::Draw, vehicle::Vehicle, Direction, Update};
+use crate::{
+    animation::{self, Animation, SpriteSheet},
+    controller::{AnalogState, ControlScheme},
+    draw::Draw,
+    vehicle::{Door, Occupant, Seat, Vehicle},
+    weapons::Weapon,
+    Direction, Update,
+};
 use macroquad::prelude::*;
 
 pub const PLAYER_TEXTURE_SCALING_FAC: f32 = 4.;
 pub const PLAYER_SPRITE_SIZE: f32 = 16.;
 pub const SCALED_PLAYER_SPRITE_SIZE: f32 = PLAYER_TEXTURE_SCALING_FAC * PLAYER_SPRITE_SIZE;
+pub const MAX_HEALTH: f32 = 100.;
+pub const MAX_ARMOR: f32 = 100.;
+/// Moving faster than this shows the run cycle instead of walking.
+const RUN_ANIMATION_SPEED: f32 = 200.;
+
+/// How close to a door the player has to be to start getting into a vehicle.
+pub const ENTER_RANGE: f32 = 250.;
+const DOOR_REACHED_DISTANCE: f32 = 20.;
+pub const BREAK_WINDOW_DURATION: f32 = 1.5;
+pub const PULL_OUT_DURATION: f32 = 1.;
+pub const GET_IN_DURATION: f32 = 0.6;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EntryStage {
+    WalkingToDoor,
+    BreakingWindow { time_left: f32 },
+    PullingOutDriver { time_left: f32 },
+    GettingIn { time_left: f32 },
+}
+
+/// The player's way into a vehicle: walking up to the door, breaking the window of locked
+/// vehicles, pulling out whoever is driving and finally climbing in.
+pub struct VehicleEntry {
+    pub vehicle: Rc<RefCell<Vehicle>>,
+    pub door: Door,
+    pub seat: Seat,
+    pub stage: EntryStage,
+}
+
+impl VehicleEntry {
+    fn pull_out_driver(&mut self) -> EntryEvent {
+        self.stage = EntryStage::PullingOutDriver {
+            time_left: PULL_OUT_DURATION,
+        };
+        EntryEvent::DriverPulledOut(self.vehicle.clone())
+    }
+}
+
+/// Things happening during a vehicle entry that the rest of the world has to react to.
+pub enum EntryEvent {
+    WindowBroken(Rc<RefCell<Vehicle>>),
+    DriverPulledOut(Rc<RefCell<Vehicle>>),
+    Entered(Rc<RefCell<Vehicle>>),
+}
 
 pub struct Player {
     pub pos: Vec2,
     pub facing: Direction,
     pub texture: Texture2D,
     pub movement_speed: f32,
+    pub health: f32,
+    /// Takes damage before health does.
+    pub armor: f32,
     pub in_vehicle: Option<Rc<RefCell<Vehicle>>>,
+    /// Where the player sits in `in_vehicle`.
+    pub seat: Seat,
+    pub entering: Option<VehicleEntry>,
+    pub money: u32,
+    pub weapons: Vec<Weapon>,
+    /// Index into `weapons` of the weapon in hand.
+    pub current_weapon: usize,
+    /// Ids of the missions the player has finished.
+    pub completed_missions: Vec<String>,
+    pub control_scheme: ControlScheme,
+    pub animation: Animation,
+    /// Where the player was at the last update, to tell how fast they are moving.
+    last_pos: Vec2,
 }
 
 impl Player {
-    pub fn new(texture: Texture2D) -> Self {
-        texture.set_filter(FilterMode::Nearest);
+    pub fn new(sprite: SpriteSheet) -> Self {
         Player {
             pos: (0., 0.).into(),
             facing: Direction::South,
             movement_speed: 300.,
-            texture,
+            health: MAX_HEALTH,
+            armor: 0.,
+            texture: sprite.texture,
             in_vehicle: None,
+            seat: Seat::Driver,
+            entering: None,
+            money: 0,
+            weapons: Vec::new(),
+            current_weapon: 0,
+            completed_missions: Vec::new(),
+            control_scheme: ControlScheme::default(),
+            animation: Animation::new(sprite.animations),
+            last_pos: Vec2::ZERO,
         }
     }
 
+    /// Loses `amount` of armor, and of health once the armor is gone.
+    pub fn take_damage(&mut self, amount: f32) {
+        let absorbed = amount.min(self.armor);
+        self.armor -= absorbed;
+        self.health = (self.health - (amount - absorbed)).max(0.);
+    }
+
     pub fn pos_add(&mut self, translation: Vec2) {
         self.pos.x += translation.x.ceil();
         self.pos.y += translation.y.ceil();
@@ -37,44 +121,236 @@ impl Player {
             return;
         }
         let screen_pos = (
-            screen_width() / 2. - self.texture.width() / 2.,
-            screen_height() / 2. - self.texture.height() / 2.,
+            screen_width() / 2. - SCALED_PLAYER_SPRITE_SIZE / 2.,
+            screen_height() / 2. - SCALED_PLAYER_SPRITE_SIZE / 2.,
         )
             .into();
         self.draw_at_screen_space(screen_pos);
     }
 
-    pub fn handle_controls(&mut self, facing: Option<Direction>) {
+    /// The vehicle the player is driving, not just sitting in.
+    pub fn driving(&self) -> Option<&Rc<RefCell<Vehicle>>> {
+        self.in_vehicle.as_ref().filter(|_| self.seat == Seat::Driver)
+    }
+
+    pub fn handle_controls(&mut self, facing: Option<Direction>, dt: f32) {
+        if self.in_vehicle.is_some() && self.seat != Seat::Driver {
+            return;
+        }
         if let Some(vehicle) = self.in_vehicle.clone() {
             let mut vehicle = vehicle.borrow_mut();
-            vehicle.handle_controls(facing);
-        } else {
-            if let Some(facing) = facing {
+            match self.control_scheme {
+                ControlScheme::Tank => vehicle.handle_controls(facing),
+                ControlScheme::ScreenRelative => vehicle.handle_screen_controls(facing.map(|f| f.as_vector()), 1.),
+            }
+        } else if let Some(facing) = facing {
+            // Walking away aborts getting into a vehicle, unless the player is already climbing in.
+            if !matches!(
+                self.entering.as_ref().map(|e| e.stage),
+                Some(EntryStage::GettingIn { .. })
+            ) {
+                self.entering = None;
                 self.facing = facing;
-                self.pos_add(facing.as_vector() * self.movement_speed * get_frame_time());
+                self.pos_add(facing.as_vector() * self.movement_speed * dt);
+            }
+        }
+    }
+
+    /// Like `handle_controls`, but steering, throttle and walking direction come from a gamepad.
+    pub fn handle_analog(&mut self, analog: &AnalogState, dt: f32) {
+        let stick = analog.stick();
+        if self.in_vehicle.is_some() && self.seat != Seat::Driver {
+            return;
+        }
+        if let Some(vehicle) = self.in_vehicle.clone() {
+            let mut vehicle = vehicle.borrow_mut();
+            match self.control_scheme {
+                ControlScheme::Tank => {
+                    vehicle.steer(stick.x);
+                    vehicle.throttle = analog.throttle - analog.brake;
+                }
+                // How far the stick is pushed is how much gas is given
+                ControlScheme::ScreenRelative => vehicle.handle_screen_controls(Some(stick), stick.length().min(1.)),
             }
+        } else if stick != Vec2::ZERO
+            && !matches!(
+                self.entering.as_ref().map(|e| e.stage),
+                Some(EntryStage::GettingIn { .. })
+            )
+        {
+            self.entering = None;
+            self.facing = Direction::from_vector(stick);
+            // Not `pos_add`, its rounding would make some directions faster than others
+            self.pos += stick.clamp_length_max(1.) * self.movement_speed * dt;
         }
     }
 
-    pub fn enter_vehicle(&mut self, vehicle: Rc<RefCell<Vehicle>>) {
-        vehicle.borrow_mut().entered = true;
+    pub fn weapon(&self) -> Option<&Weapon> {
+        self.weapons.get(self.current_weapon)
+    }
+
+    pub fn weapon_mut(&mut self) -> Option<&mut Weapon> {
+        self.weapons.get_mut(self.current_weapon)
+    }
+
+    pub fn enter_vehicle(&mut self, vehicle: Rc<RefCell<Vehicle>>, seat: Seat) {
+        let mut vehicle_mut = vehicle.borrow_mut();
+        vehicle_mut.set_occupant(seat, Some(Occupant::Player));
+        // Only cars the player drove are theirs
+        if seat == Seat::Driver {
+            vehicle_mut.owned = true;
+        }
+        drop(vehicle_mut);
+        self.entering = None;
+        self.seat = seat;
         self.in_vehicle = Some(vehicle)
     }
 
     pub fn leave_vehicle(&mut self, vehicle: Rc<RefCell<Vehicle>>) {
         let mut vehicle = vehicle.borrow_mut();
-        vehicle.entered = false;
-        vehicle.steer_neutral();
-        vehicle.throttle = 0.;
+        vehicle.set_occupant(self.seat, None);
+        if self.seat == Seat::Driver {
+            vehicle.steer_neutral();
+            vehicle.throttle = 0.;
+            vehicle.handbrake = false;
+        }
+        self.pos = vehicle.door_position(Vehicle::seat_door(self.seat));
         self.in_vehicle = None;
+        self.seat = Seat::Driver;
+    }
+
+    /// Starts walking to the given door of `vehicle` to get into `seat`.
+    pub fn start_entering(&mut self, vehicle: Rc<RefCell<Vehicle>>, door: Door, seat: Seat) {
+        self.entering = Some(VehicleEntry {
+            vehicle,
+            door,
+            seat,
+            stage: EntryStage::WalkingToDoor,
+        });
+    }
+
+    pub fn cancel_entering(&mut self) {
+        self.entering = None;
+    }
+
+    /// Advances a vehicle entry in progress.
+    pub fn update_entry(&mut self, dt: f32) -> Option<EntryEvent> {
+        let entry = self.entering.as_mut()?;
+        let vehicle = entry.vehicle.clone();
+        let door_pos = vehicle.borrow().door_position(entry.door);
+        match entry.stage {
+            EntryStage::WalkingToDoor => {
+                let to_door = door_pos - self.pos;
+                if to_door.length() > ENTER_RANGE * 2. {
+                    // The vehicle got away
+                    self.entering = None;
+                    return None;
+                }
+                if to_door.length() > DOOR_REACHED_DISTANCE {
+                    let step = to_door.normalize() * self.movement_speed * dt;
+                    self.facing = Direction::from_vector(to_door);
+                    self.pos += step.clamp_length_max(to_door.length());
+                    return None;
+                }
+                self.pos = door_pos;
+                let mut vehicle = vehicle.borrow_mut();
+                entry.stage = if vehicle.locked {
+                    vehicle.trigger_alarm();
+                    EntryStage::BreakingWindow {
+                        time_left: BREAK_WINDOW_DURATION,
+                    }
+                } else if entry.seat == Seat::Driver && vehicle.driver == Some(Occupant::Npc) {
+                    return Some(entry.pull_out_driver());
+                } else if vehicle.occupant(entry.seat).is_some() {
+                    // Someone else took the seat
+                    self.entering = None;
+                    return None;
+                } else {
+                    EntryStage::GettingIn {
+                        time_left: GET_IN_DURATION,
+                    }
+                };
+                None
+            }
+            EntryStage::BreakingWindow { time_left } => {
+                self.pos = door_pos;
+                if time_left > dt {
+                    entry.stage = EntryStage::BreakingWindow {
+                        time_left: time_left - dt,
+                    };
+                    return None;
+                }
+                vehicle.borrow_mut().locked = false;
+                // Standing at the now unlocked door, so the next update carries on from there
+                entry.stage = EntryStage::WalkingToDoor;
+                Some(EntryEvent::WindowBroken(vehicle))
+            }
+            EntryStage::PullingOutDriver { time_left } => {
+                self.pos = door_pos;
+                entry.stage = if time_left > dt {
+                    EntryStage::PullingOutDriver {
+                        time_left: time_left - dt,
+                    }
+                } else {
+                    EntryStage::GettingIn {
+                        time_left: GET_IN_DURATION,
+                    }
+                };
+                None
+            }
+            EntryStage::GettingIn { time_left } => {
+                if time_left > dt {
+                    let progress = 1. - (time_left - dt) / GET_IN_DURATION;
+                    self.pos = door_pos.lerp(vehicle.borrow().position(), progress);
+                    entry.stage = EntryStage::GettingIn {
+                        time_left: time_left - dt,
+                    };
+                    return None;
+                }
+                let seat = entry.seat;
+                self.enter_vehicle(vehicle.clone(), seat);
+                Some(EntryEvent::Entered(vehicle))
+            }
+        }
+    }
+
+    /// Fades the player out while climbing into a vehicle.
+    fn tint(&self) -> Color {
+        match self.entering.as_ref().map(|e| e.stage) {
+            Some(EntryStage::GettingIn { time_left }) => {
+                Color::new(1., 1., 1., time_left / GET_IN_DURATION)
+            }
+            _ => WHITE,
+        }
     }
 }
 
 impl Update for Player {
-    fn update(&mut self) {
+    fn update(&mut self, dt: f32) {
         if let Some(vehicle) = self.in_vehicle.clone() {
             self.pos = vehicle.borrow().position();
         }
+        let speed = self.pos.distance(self.last_pos) / dt;
+        self.last_pos = self.pos;
+        let getting_in = self
+            .entering
+            .as_ref()
+            .is_some_and(|entry| entry.stage != EntryStage::WalkingToDoor);
+        let clip = if self.health <= 0. {
+            animation::DIE
+        } else if getting_in {
+            animation::ENTER_CAR
+        } else if self.in_vehicle.is_some() {
+            animation::IDLE
+        } else if speed > RUN_ANIMATION_SPEED {
+            animation::RUN
+        } else if speed > 0. {
+            animation::WALK
+        } else {
+            animation::IDLE
+        };
+        self.animation.show(clip);
+        self.animation.update(dt);
     }
 }
 
@@ -92,27 +368,17 @@ impl Draw for Player {
     }
 
     fn draw_at_screen_space(&self, screen_pos: Vec2) {
-        // TODO: Add dedicated sprites for all facings
-        let x_texture_offset = match self.facing {
-            Direction::North => 2. * PLAYER_SPRITE_SIZE,
-            Direction::NorthEast => 2. * PLAYER_SPRITE_SIZE,
-            Direction::East => PLAYER_SPRITE_SIZE,
-            Direction::SouthEast => 0.,
-            Direction::South => 0.,
-            Direction::SouthWest => 0.,
-            Direction::West => 3. * PLAYER_SPRITE_SIZE,
-            Direction::NorthWest => 2. * PLAYER_SPRITE_SIZE,
-        };
+        let x_texture_offset = sprite_x_offset(self.facing);
         draw_texture_ex(
             &self.texture,
             screen_pos.x,
             screen_pos.y,
-            WHITE,
+            self.tint(),
             DrawTextureParams {
                 dest_size: Some((SCALED_PLAYER_SPRITE_SIZE, SCALED_PLAYER_SPRITE_SIZE).into()),
                 source: Some(Rect {
                     x: x_texture_offset,
-                    y: 0.,
+                    y: self.animation.row() as f32 * PLAYER_SPRITE_SIZE,
                     w: PLAYER_SPRITE_SIZE,
                     h: PLAYER_SPRITE_SIZE,
                 }),
@@ -125,3 +391,19 @@ impl Draw for Player {
         self.pos
     }
 }
+
+/// Horizontal offset of the frame for `facing` in a character sprite sheet.
+/// Pedestrians share the player's sheet layout.
+pub fn sprite_x_offset(facing: Direction) -> f32 {
+    // TODO: Add dedicated sprites for all facings
+    match facing {
+        Direction::North => 2. * PLAYER_SPRITE_SIZE,
+        Direction::NorthEast => 2. * PLAYER_SPRITE_SIZE,
+        Direction::East => PLAYER_SPRITE_SIZE,
+        Direction::SouthEast => 0.,
+        Direction::South => 0.,
+        Direction::SouthWest => 0.,
+        Direction::West => 3. * PLAYER_SPRITE_SIZE,
+        Direction::NorthWest => 2. * PLAYER_SPRITE_SIZE,
+    }
+}
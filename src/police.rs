@@ -0,0 +1,506 @@
+use std::{cell::RefCell, rc::Rc};
+
+use macroquad::prelude::*;
+
+use crate::{
+    animation::{self, SpriteSheet},
+    draw::Draw,
+    intersection::Intersections,
+    map::Map,
+    navigation::{Navigation, Route},
+    pedestrian::Pedestrian,
+    rng::Rng,
+    road::{EdgeId, RoadGraph, TurnKind, LANE_OFFSET},
+    traffic::{drive_towards, set_speed, TrafficDriver, DESPAWN_RADIUS, SPAWN_RADIUS_MIN},
+    vehicle::{Door, Occupant, Vehicle},
+    wanted::Tactics,
+};
+
+/// The police see everything within this distance of one of their units or officers.
+pub const SIGHT_RADIUS: f32 = 1400.;
+pub const PURSUIT_SPEED: f32 = 14.;
+/// Units closer than this to the player leave the road network and drive straight at them.
+const DIRECT_CHASE_DISTANCE: f32 = 700.;
+const REPLAN_INTERVAL: f32 = 1.;
+const ROUTE_LOOKAHEAD: f32 = 300.;
+
+/// Units this close to the player's vehicle go for a PIT manoeuvre when ramming is allowed.
+const RAM_DISTANCE: f32 = 600.;
+/// How much faster than the player's vehicle a unit goes when ramming.
+const RAM_SPEED_BONUS: f32 = 4.;
+/// Point behind the center of the player's vehicle that ramming units aim for.
+const RAM_REAR_OFFSET: f32 = 80.;
+
+/// Roadblocks go up at the first intersection at least this far ahead of the player.
+const ROADBLOCK_MIN_DISTANCE: f32 = 1500.;
+/// Distance of a roadblock from the intersection it blocks.
+const ROADBLOCK_SETBACK: f32 = 300.;
+/// Roadblocks the player has left this far behind get cleared up.
+const ROADBLOCK_CLEAR_DISTANCE: f32 = 2000.;
+const ROADBLOCK_COOLDOWN: f32 = 20.;
+
+/// Crews get out of their car when they stop this close to the player.
+const EXIT_DISTANCE: f32 = 500.;
+/// Crews get back into their car when the player is further away than this.
+const RETURN_DISTANCE: f32 = 1000.;
+const OFFICER_WALK_SPEED: f32 = 200.;
+const OFFICER_RANGE: f32 = 700.;
+const OFFICER_FIRE_INTERVAL: f32 = 1.2;
+pub const OFFICER_SHOT_DAMAGE: f32 = 6.;
+const TRACER_DURATION: f32 = 0.1;
+
+/// Who the police are after.
+pub struct PursuitTarget {
+    pub pos: Vec2,
+    /// The vehicle the player is driving, if any.
+    pub vehicle: Option<Rc<RefCell<Vehicle>>>,
+}
+
+/// A shot fired by an officer.
+#[derive(Debug, Clone, Copy)]
+pub struct Shot {
+    pub from: Vec2,
+    pub to: Vec2,
+    pub hit: bool,
+}
+
+/// A police car and its crew.
+pub struct PoliceUnit {
+    pub vehicle: Rc<RefCell<Vehicle>>,
+    patrol: TrafficDriver,
+    route: Option<Rc<Route>>,
+    replan_in: f32,
+    /// The crew is out on foot.
+    crew_out: bool,
+}
+
+impl PoliceUnit {
+    pub fn new(vehicle: Rc<RefCell<Vehicle>>, roads: &RoadGraph, edge: EdgeId, distance: f32, rng: &mut Rng) -> Self {
+        PoliceUnit {
+            patrol: TrafficDriver::new(vehicle.clone(), roads, edge, distance, rng),
+            vehicle,
+            route: None,
+            replan_in: 0.,
+            crew_out: false,
+        }
+    }
+
+    pub fn position(&self) -> Vec2 {
+        self.vehicle.borrow().position()
+    }
+
+    /// Drives around like regular traffic.
+    fn patrol(&mut self, roads: &RoadGraph, map: &Map, intersections: &Intersections, obstacles: &[Vec2], rng: &mut Rng) {
+        if self.vehicle.borrow().siren {
+            // Back from a pursuit
+            self.vehicle.borrow_mut().siren = false;
+            self.route = None;
+            self.patrol.rejoin_lanes(roads, map, rng);
+        }
+        self.patrol.drive(roads, intersections, obstacles, rng);
+    }
+
+    /// Follows a route to the target, which is replanned regularly as the target moves.
+    /// Close up the unit either tails the target or, if allowed to, rams it.
+    #[allow(clippy::too_many_arguments)]
+    fn pursue(
+        &mut self,
+        roads: &RoadGraph,
+        map: &Map,
+        navigation: &mut Navigation,
+        target: &PursuitTarget,
+        tactics: Tactics,
+        obstacles: &[(Vec2, bool)],
+        dt: f32,
+    ) {
+        let mut vehicle = self.vehicle.borrow_mut();
+        vehicle.siren = true;
+        let pos = vehicle.position();
+        let distance = pos.distance(target.pos);
+        let others: Vec<Vec2> = obstacles.iter().map(|(pos, _)| *pos).collect();
+
+        if let Some(target_vehicle) = target.vehicle.as_ref().filter(|_| tactics.ram) {
+            if distance < RAM_DISTANCE {
+                let target_vehicle = target_vehicle.borrow();
+                // Aim for the rear quarter on the side we're coming from, so the hit spins them out
+                let side = Vec2::new(-target_vehicle.rotation.y, target_vehicle.rotation.x);
+                let side = side * (pos - target.pos).dot(side).signum() * LANE_OFFSET / 2.;
+                let aim = target.pos - target_vehicle.rotation * RAM_REAR_OFFSET + side;
+                let without_target: Vec<Vec2> = obstacles
+                    .iter()
+                    .filter(|(_, is_target)| !is_target)
+                    .map(|(pos, _)| *pos)
+                    .collect();
+                drive_towards(
+                    &mut vehicle,
+                    aim,
+                    target_vehicle.velocity + RAM_SPEED_BONUS,
+                    &without_target,
+                );
+                return;
+            }
+        }
+        if distance < DIRECT_CHASE_DISTANCE {
+            drive_towards(&mut vehicle, target.pos, PURSUIT_SPEED, &others);
+            return;
+        }
+
+        self.replan_in -= dt;
+        let off_route = self.route.as_ref().is_none_or(|route| route.is_off_route(pos));
+        if off_route || self.replan_in <= 0. {
+            self.replan_in = REPLAN_INTERVAL;
+            self.route = navigation.vehicle_route(roads, map, pos, Some(vehicle.rotation), target.pos);
+        }
+        let waypoint = match &self.route {
+            Some(route) => route.point_ahead(pos, ROUTE_LOOKAHEAD),
+            None => target.pos,
+        };
+        drive_towards(&mut vehicle, waypoint, PURSUIT_SPEED, &others);
+    }
+
+    /// Whether the crew should get out to go after the player on foot.
+    fn should_get_out(&self, target: &PursuitTarget, tactics: Tactics) -> bool {
+        let vehicle = self.vehicle.borrow();
+        tactics.shoot
+            && !self.crew_out
+            && vehicle.driver == Some(Occupant::Npc)
+            && vehicle.velocity < 1.
+            && vehicle.position().distance(target.pos) < EXIT_DISTANCE
+    }
+}
+
+/// A police officer out of their car.
+pub struct Officer {
+    pub body: Pedestrian,
+    /// The car the officer came in, unless someone took it.
+    pub vehicle: Option<Rc<RefCell<Vehicle>>>,
+    cooldown: f32,
+    tracer: Option<(Shot, f32)>,
+}
+
+impl Officer {
+    fn new(sprite: SpriteSheet, pos: Vec2, vehicle: Rc<RefCell<Vehicle>>) -> Self {
+        let mut body = Pedestrian::new(sprite, pos);
+        body.tint = SKYBLUE;
+        Officer {
+            body,
+            vehicle: Some(vehicle),
+            cooldown: OFFICER_FIRE_INTERVAL,
+            tracer: None,
+        }
+    }
+
+    /// Walks into range of the target and shoots at it. Returns the shot if one was fired.
+    fn engage(&mut self, target: Vec2, rng: &mut Rng, dt: f32) -> Option<Shot> {
+        self.cooldown = (self.cooldown - dt).max(0.);
+        let to_target = target - self.body.pos;
+        if to_target.length() > OFFICER_RANGE {
+            self.body.walk(to_target.normalize_or_zero(), OFFICER_WALK_SPEED, dt);
+            return None;
+        }
+        self.body.facing = crate::Direction::from_vector(to_target);
+        if self.cooldown > 0. {
+            return None;
+        }
+        self.cooldown = OFFICER_FIRE_INTERVAL;
+        // Less accurate the further away the target is
+        let hit_chance = (1. - to_target.length() / OFFICER_RANGE).clamp(0.15, 0.7);
+        let hit = rng.chance(hit_chance);
+        let miss = Vec2::new(rng.gen_range(-60., 60.), rng.gen_range(-60., 60.));
+        let shot = Shot {
+            from: self.body.pos,
+            to: if hit { target } else { target + miss },
+            hit,
+        };
+        self.tracer = Some((shot, TRACER_DURATION));
+        self.body.animation.play(animation::SHOOT);
+        Some(shot)
+    }
+
+    /// Walks back to the car. Returns whether the officer reached it.
+    fn return_to_vehicle(&mut self, dt: f32) -> bool {
+        let Some(vehicle) = &self.vehicle else {
+            return false;
+        };
+        let door = vehicle.borrow().door_position(Door::Left);
+        let to_door = door - self.body.pos;
+        if to_door.length() < 20. {
+            return true;
+        }
+        self.body.walk(to_door.normalize_or_zero(), OFFICER_WALK_SPEED, dt);
+        false
+    }
+
+    fn draw(&self, camera_pos: Vec2) {
+        self.body.draw_at_world_space(camera_pos);
+        if let Some((shot, _)) = self.tracer {
+            let offset = Vec2::new(screen_width(), screen_height()) / 2. - camera_pos;
+            let (from, to) = (shot.from + offset, shot.to + offset);
+            draw_line(from.x, from.y, to.x, to.y, 2., YELLOW);
+        }
+    }
+}
+
+/// Police cars parked across the road.
+pub struct Roadblock {
+    pub pos: Vec2,
+    pub vehicles: Vec<Rc<RefCell<Vehicle>>>,
+}
+
+/// Where to put a roadblock for someone driving at `pos` in direction `heading`:
+/// on the road straight on after the first intersection far enough ahead.
+/// Returns the position and the direction of the blocked road.
+pub fn roadblock_site(roads: &RoadGraph, map: &Map, pos: Vec2, heading: Vec2) -> Option<(Vec2, Vec2)> {
+    let lane = roads.nearest_lane(map, pos, Some(heading))?;
+    let mut edge = lane.edge;
+    let mut ahead = roads.edge(edge).length - lane.distance;
+    // A handful of intersections is plenty, the player will have turned off by then anyway
+    for _ in 0..8 {
+        let next = roads
+            .edge(edge)
+            .turns
+            .iter()
+            .find(|turn| turn.kind == TurnKind::Straight)?
+            .to;
+        if ahead >= ROADBLOCK_MIN_DISTANCE {
+            let road = roads.edge(next);
+            let (lane_pos, direction) = road.point_at(ROADBLOCK_SETBACK.min(road.length / 2.));
+            // Center of the road rather than of the lane
+            let left = Vec2::new(direction.y, -direction.x);
+            return Some((lane_pos + left * LANE_OFFSET, direction));
+        }
+        ahead += roads.edge(next).length;
+        edge = next;
+    }
+    None
+}
+
+/// What the police did during one update.
+#[derive(Default)]
+pub struct PoliceEvents {
+    pub shots: Vec<Shot>,
+}
+
+#[derive(Default)]
+pub struct Police {
+    pub units: Vec<PoliceUnit>,
+    pub officers: Vec<Officer>,
+    pub roadblock: Option<Roadblock>,
+    roadblock_cooldown: f32,
+    /// Vehicle models randomly picked from for newly spawned police cars. Nothing spawns while this is empty.
+    pub vehicle_models: Vec<String>,
+    /// Sprites for officers on foot. Crews stay in their cars while this is empty.
+    pub officer_sprites: Vec<SpriteSheet>,
+}
+
+impl Police {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn can_see(&self, pos: Vec2) -> bool {
+        self.units
+            .iter()
+            .map(|unit| unit.position())
+            .chain(self.officers.iter().filter(|o| !o.body.is_down()).map(|o| o.body.pos))
+            .any(|police| police.distance(pos) < SIGHT_RADIUS)
+    }
+
+    /// Removes the unit driving `vehicle`, e.g. because the player pulled the officer out.
+    /// Officers of that unit out on foot stay around without a car.
+    pub fn remove_unit(&mut self, vehicle: &Rc<RefCell<Vehicle>>) -> Option<PoliceUnit> {
+        for officer in &mut self.officers {
+            if officer.vehicle.as_ref().is_some_and(|v| Rc::ptr_eq(v, vehicle)) {
+                officer.vehicle = None;
+            }
+        }
+        let index = self
+            .units
+            .iter()
+            .position(|u| Rc::ptr_eq(&u.vehicle, vehicle))?;
+        Some(self.units.remove(index))
+    }
+
+    /// Units that are too far away, and units beyond the `wanted` count that are out of view.
+    /// They are removed from the police together with their crews.
+    pub fn take_dismissed(&mut self, player_pos: Vec2, wanted: usize) -> Vec<PoliceUnit> {
+        self.units.sort_by(|a, b| {
+            a.position()
+                .distance(player_pos)
+                .total_cmp(&b.position().distance(player_pos))
+        });
+        let mut dismissed = Vec::new();
+        let mut index = 0;
+        while index < self.units.len() {
+            let distance = self.units[index].position().distance(player_pos);
+            let excess = index >= wanted && distance > SPAWN_RADIUS_MIN && !self.units[index].crew_out;
+            if distance > DESPAWN_RADIUS || excess {
+                dismissed.push(self.units.remove(index));
+            } else {
+                index += 1;
+            }
+        }
+        self.officers.retain(|officer| {
+            let abandoned = officer.vehicle.as_ref().is_some_and(|v| {
+                dismissed.iter().any(|unit| Rc::ptr_eq(&unit.vehicle, v))
+            });
+            !abandoned && officer.body.pos.distance(player_pos) <= DESPAWN_RADIUS
+        });
+        dismissed
+    }
+
+    /// Whether a new roadblock may be put up. Starts the cooldown if so.
+    pub fn roadblock_ready(&mut self, dt: f32) -> bool {
+        self.roadblock_cooldown = (self.roadblock_cooldown - dt).max(0.);
+        if self.roadblock.is_some() || self.roadblock_cooldown > 0. {
+            return false;
+        }
+        self.roadblock_cooldown = ROADBLOCK_COOLDOWN;
+        true
+    }
+
+    /// Takes down the roadblock once it is no longer needed. Returns its vehicles.
+    pub fn take_cleared_roadblock(&mut self, player_pos: Vec2, wanted: bool) -> Vec<Rc<RefCell<Vehicle>>> {
+        let cleared = self
+            .roadblock
+            .as_ref()
+            .is_some_and(|r| !wanted || r.pos.distance(player_pos) > ROADBLOCK_CLEAR_DISTANCE);
+        if !cleared {
+            return Vec::new();
+        }
+        self.roadblock.take().map(|r| r.vehicles).unwrap_or_default()
+    }
+
+    /// Pursues `target` if there is one, ignoring traffic lights, otherwise patrols.
+    /// Crews get out and shoot when the tactics allow it.
+    #[allow(clippy::too_many_arguments)]
+    pub fn update(
+        &mut self,
+        roads: &RoadGraph,
+        map: &Map,
+        navigation: &mut Navigation,
+        intersections: &Intersections,
+        vehicles: &[Rc<RefCell<Vehicle>>],
+        pedestrians: &[Vec2],
+        target: Option<PursuitTarget>,
+        tactics: Tactics,
+        rng: &mut Rng,
+        dt: f32,
+    ) -> PoliceEvents {
+        let mut events = PoliceEvents::default();
+        // Cars the player took while the crew was out on foot
+        let stolen: Vec<_> = self
+            .units
+            .iter()
+            .filter(|u| u.vehicle.borrow().driver == Some(Occupant::Player))
+            .map(|u| u.vehicle.clone())
+            .collect();
+        for vehicle in &stolen {
+            self.remove_unit(vehicle);
+        }
+        for unit in &mut self.units {
+            if unit.crew_out {
+                let mut vehicle = unit.vehicle.borrow_mut();
+                set_speed(&mut vehicle, 0.);
+                vehicle.steer_neutral();
+                continue;
+            }
+            let obstacles: Vec<(Vec2, bool)> = vehicles
+                .iter()
+                .filter(|v| !Rc::ptr_eq(v, &unit.vehicle))
+                .map(|v| {
+                    let is_target = target
+                        .as_ref()
+                        .and_then(|t| t.vehicle.as_ref())
+                        .is_some_and(|t| Rc::ptr_eq(t, v));
+                    (v.borrow().position(), is_target)
+                })
+                .chain(pedestrians.iter().map(|p| (*p, false)))
+                .collect();
+            match &target {
+                Some(target) => {
+                    if unit.should_get_out(target, tactics) {
+                        if let Some(sprite) = self.officer_sprites.first().cloned() {
+                            let mut vehicle = unit.vehicle.borrow_mut();
+                            vehicle.driver = None;
+                            vehicle.throttle = 0.;
+                            let door = vehicle.door_position(Door::Left);
+                            self.officers.push(Officer::new(sprite, door, unit.vehicle.clone()));
+                            unit.crew_out = true;
+                            continue;
+                        }
+                    }
+                    unit.pursue(roads, map, navigation, target, tactics, &obstacles, dt)
+                }
+                None => {
+                    let obstacles: Vec<Vec2> = obstacles.iter().map(|(pos, _)| *pos).collect();
+                    unit.patrol(roads, map, intersections, &obstacles, rng)
+                }
+            }
+        }
+
+        let mut boarded = Vec::new();
+        for (index, officer) in self.officers.iter_mut().enumerate() {
+            if let Some((_, time_left)) = &mut officer.tracer {
+                *time_left -= dt;
+                if *time_left <= 0. {
+                    officer.tracer = None;
+                }
+            }
+            if officer.body.is_down() {
+                officer.body.animate(dt);
+                continue;
+            }
+            let engaging = target
+                .as_ref()
+                .filter(|t| tactics.shoot && t.pos.distance(officer.body.pos) < RETURN_DISTANCE);
+            match engaging {
+                Some(target) => events.shots.extend(officer.engage(target.pos, rng, dt)),
+                None => {
+                    let vehicle_free = officer
+                        .vehicle
+                        .as_ref()
+                        .is_some_and(|v| v.borrow().driver.is_none());
+                    if vehicle_free && officer.return_to_vehicle(dt) {
+                        boarded.push(index);
+                    }
+                }
+            }
+            officer.body.animate(dt);
+        }
+        for index in boarded.into_iter().rev() {
+            let officer = self.officers.remove(index);
+            let Some(vehicle) = officer.vehicle else {
+                continue;
+            };
+            vehicle.borrow_mut().driver = Some(Occupant::Npc);
+            if let Some(unit) = self.units.iter_mut().find(|u| Rc::ptr_eq(&u.vehicle, &vehicle)) {
+                unit.crew_out = false;
+                unit.patrol.rejoin_lanes(roads, map, rng);
+            }
+        }
+        // Nobody is coming back for a car whose crew was shot, it is left standing with the siren off
+        let abandoned: Vec<_> = self
+            .units
+            .iter()
+            .filter(|u| {
+                let crew_standing = self.officers.iter().any(|o| {
+                    !o.body.is_down() && o.vehicle.as_ref().is_some_and(|v| Rc::ptr_eq(v, &u.vehicle))
+                });
+                u.crew_out && !crew_standing
+            })
+            .map(|u| u.vehicle.clone())
+            .collect();
+        for vehicle in &abandoned {
+            vehicle.borrow_mut().siren = false;
+            self.remove_unit(vehicle);
+        }
+        events
+    }
+
+    pub fn draw(&self, camera_pos: Vec2) {
+        for officer in &self.officers {
+            officer.draw(camera_pos);
+        }
+    }
+}
@@ -0,0 +1,129 @@
+use std::{fs, path::PathBuf};
+
+use nanoserde::{DeJson, SerJson};
+
+use crate::{
+    assets::Manifest,
+    game::{Game, Sprites, TickInput},
+    map::{Map, MapSource},
+    mission::Missions,
+    script::Scripts,
+};
+
+pub const REPLAY_VERSION: u32 = 2;
+const REPLAY_DIR: &str = "replays";
+
+/// The same input held for a number of consecutive ticks.
+#[derive(Debug, Clone, SerJson, DeJson)]
+pub struct InputRun {
+    pub ticks: u32,
+    pub input: TickInput,
+}
+
+/// Inputs of a session from the start of a new game, along with what the game looked like at the end,
+/// so a bug can be reproduced by replaying it.
+#[derive(Debug, Clone, SerJson, DeJson)]
+pub struct Recording {
+    pub version: u32,
+    pub seed: u64,
+    pub map: MapSource,
+    pub inputs: Vec<InputRun>,
+    /// Number of ticks recorded and the checksum of the game after the last one.
+    pub ticks: u64,
+    pub checksum: u64,
+}
+
+/// Records the input of every tick of a game started with `Game::new`.
+pub struct Recorder {
+    seed: u64,
+    map: MapSource,
+    inputs: Vec<InputRun>,
+}
+
+impl Recorder {
+    pub fn new(seed: u64, map: MapSource) -> Self {
+        Recorder {
+            seed,
+            map,
+            inputs: Vec::new(),
+        }
+    }
+
+    pub fn record(&mut self, input: &TickInput) {
+        match self.inputs.last_mut() {
+            Some(run) if run.input == *input => run.ticks += 1,
+            _ => self.inputs.push(InputRun {
+                ticks: 1,
+                input: *input,
+            }),
+        }
+    }
+
+    /// The recording so far, ending at the current state of `game`.
+    pub fn finish(&self, game: &Game) -> Recording {
+        Recording {
+            version: REPLAY_VERSION,
+            seed: self.seed,
+            map: self.map.clone(),
+            inputs: self.inputs.clone(),
+            ticks: game.ticks,
+            checksum: game.checksum(),
+        }
+    }
+}
+
+impl Recording {
+    pub fn load(path: &str) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("Failed to read {path}: {e}"))?;
+        let recording = Recording::deserialize_json(&text).map_err(|e| format!("Corrupt replay {path}: {e}"))?;
+        if recording.version != REPLAY_VERSION {
+            return Err(format!("Replay format {} is not supported", recording.version));
+        }
+        Ok(recording)
+    }
+
+    /// Writes the recording to a new file in the replay directory. Returns its path.
+    pub fn save(&self) -> Result<PathBuf, String> {
+        fs::create_dir_all(REPLAY_DIR).map_err(|e| format!("Failed to create {REPLAY_DIR}: {e}"))?;
+        let path = (0..)
+            .map(|n| PathBuf::from(REPLAY_DIR).join(format!("replay{n}.json")))
+            .find(|path| !path.exists())
+            .expect("ran out of replay file names");
+        fs::write(&path, self.serialize_json()).map_err(|e| format!("Failed to write {}: {e}", path.display()))?;
+        Ok(path)
+    }
+
+    /// Plays all recorded inputs into a new game, with the missions and scripts named in `manifest`.
+    pub fn replay(&self, sprites: &Sprites, manifest: &Manifest) -> Result<Game, String> {
+        let map = Map::from_source(sprites.tile_map.clone(), &self.map)?;
+        let mut game = Game::new(
+            map,
+            self.seed,
+            sprites,
+            Missions::load(&manifest.data_path("missions")?)?,
+            Scripts::load(&manifest.data_path("scripts")?)?,
+        );
+        for run in &self.inputs {
+            for _ in 0..run.ticks {
+                game.tick(&run.input);
+            }
+        }
+        Ok(game)
+    }
+
+    /// Replays without a window and checks that the game ends up exactly where it did when recorded.
+    pub fn verify(&self, manifest: &Manifest) -> Result<(), String> {
+        let game = self.replay(&Sprites::placeholder(manifest)?, manifest)?;
+        if game.ticks != self.ticks {
+            return Err(format!("Replayed {} ticks, recorded {}", game.ticks, self.ticks));
+        }
+        if game.checksum() != self.checksum {
+            return Err(format!(
+                "Replay diverged: checksum {:016x}, recorded {:016x}",
+                game.checksum(),
+                self.checksum
+            ));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,79 @@
+/// Small seedable random number generator (PCG32), so a simulation started from the same seed
+/// plays out the same way every time. Everything random in the world draws from the one owned by `World`.
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: u64,
+    increment: u64,
+}
+
+const MULTIPLIER: u64 = 6364136223846793005;
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        let mut rng = Rng {
+            state: 0,
+            increment: (seed << 1) | 1,
+        };
+        rng.next_u32();
+        rng.state = rng.state.wrapping_add(seed);
+        rng.next_u32();
+        rng
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        let old = self.state;
+        self.state = old.wrapping_mul(MULTIPLIER).wrapping_add(self.increment);
+        let xorshifted = (((old >> 18) ^ old) >> 27) as u32;
+        xorshifted.rotate_right((old >> 59) as u32)
+    }
+
+    /// Uniformly distributed in `[0, 1)`.
+    pub fn next_f32(&mut self) -> f32 {
+        (self.next_u32() >> 8) as f32 / (1u32 << 24) as f32
+    }
+
+    /// Uniformly distributed in `[low, high)`, like `macroquad::rand::gen_range`.
+    pub fn gen_range<T: SampleRange>(&mut self, low: T, high: T) -> T {
+        T::sample(self, low, high)
+    }
+
+    /// True with the given probability.
+    pub fn chance(&mut self, probability: f32) -> bool {
+        self.next_f32() < probability
+    }
+
+    pub fn choose<'a, T>(&mut self, items: &'a [T]) -> Option<&'a T> {
+        if items.is_empty() {
+            return None;
+        }
+        items.get(self.gen_range(0, items.len()))
+    }
+}
+
+pub trait SampleRange: Sized {
+    fn sample(rng: &mut Rng, low: Self, high: Self) -> Self;
+}
+
+impl SampleRange for f32 {
+    fn sample(rng: &mut Rng, low: Self, high: Self) -> Self {
+        low + (high - low) * rng.next_f32()
+    }
+}
+
+impl SampleRange for usize {
+    fn sample(rng: &mut Rng, low: Self, high: Self) -> Self {
+        if high <= low {
+            return low;
+        }
+        low + rng.next_u32() as usize % (high - low)
+    }
+}
+
+impl SampleRange for isize {
+    fn sample(rng: &mut Rng, low: Self, high: Self) -> Self {
+        if high <= low {
+            return low;
+        }
+        low + (rng.next_u32() as usize % (high - low) as usize) as isize
+    }
+}
@@ -0,0 +1,361 @@
+use std::collections::HashMap;
+
+use macroquad::prelude::*;
+
+use crate::{
+    map::{Map, TILE_TEX_SIZE_SCALED},
+    Direction,
+};
+
+/// Distance of a lane's center line from the center of the road. Traffic drives on the right.
+pub const LANE_OFFSET: f32 = TILE_TEX_SIZE_SCALED / 4.;
+
+pub type NodeId = usize;
+pub type EdgeId = usize;
+
+/// Directed lane graph derived from the road tiles of a [`Map`].
+/// Intersections (and dead ends) are nodes, the road segments between them are edges.
+/// Every segment yields one edge per driving direction.
+pub struct RoadGraph {
+    pub nodes: Vec<RoadNode>,
+    pub edges: Vec<RoadEdge>,
+    node_by_tile: HashMap<(usize, usize), NodeId>,
+    edges_by_tile: HashMap<(usize, usize), Vec<EdgeId>>,
+}
+
+pub struct RoadNode {
+    pub tile: (usize, usize),
+    pub pos: Vec2,
+    pub incoming: Vec<EdgeId>,
+    pub outgoing: Vec<EdgeId>,
+}
+
+pub struct RoadEdge {
+    pub from: NodeId,
+    pub to: NodeId,
+    /// Tiles the segment runs over, including the tiles of both nodes.
+    pub tiles: Vec<(usize, usize)>,
+    /// Center line of the lane in world space.
+    pub path: Vec<Vec2>,
+    pub length: f32,
+    /// Direction of travel when leaving `from`.
+    pub start_direction: Direction,
+    /// Direction of travel when arriving at `to`.
+    pub end_direction: Direction,
+    /// Edges a vehicle may continue on after reaching `to`.
+    pub turns: Vec<Turn>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Turn {
+    pub to: EdgeId,
+    pub kind: TurnKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TurnKind {
+    Straight,
+    Left,
+    Right,
+    UTurn,
+}
+
+impl TurnKind {
+    fn between(arriving: Direction, leaving: Direction) -> Self {
+        let arriving = arriving.as_vector();
+        let leaving = leaving.as_vector();
+        if arriving.dot(leaving) > 0.5 {
+            TurnKind::Straight
+        } else if arriving.dot(leaving) < -0.5 {
+            TurnKind::UTurn
+        } else if arriving.perp_dot(leaving) > 0. {
+            // y points down, so a positive perp dot product is a clockwise (right) turn
+            TurnKind::Right
+        } else {
+            TurnKind::Left
+        }
+    }
+}
+
+/// A point on a lane found by [`RoadGraph::nearest_lane`].
+#[derive(Debug, Clone, Copy)]
+pub struct LanePoint {
+    pub edge: EdgeId,
+    /// Distance along the edge from its start.
+    pub distance: f32,
+    pub pos: Vec2,
+    /// Direction of travel at this point.
+    pub direction: Vec2,
+}
+
+impl RoadGraph {
+    pub fn from_map(map: &Map) -> Self {
+        let mut graph = RoadGraph {
+            nodes: Vec::new(),
+            edges: Vec::new(),
+            node_by_tile: HashMap::new(),
+            edges_by_tile: HashMap::new(),
+        };
+
+        for (index, _) in map.indexed_tiles() {
+            let connections = Direction::CARDINALS
+                .iter()
+                .filter(|d| map.road_connects(index, **d))
+                .count();
+            if connections > 0 && connections != 2 {
+                graph.add_node(map, index);
+            }
+        }
+        for node in 0..graph.nodes.len() {
+            graph.trace_edges(map, node);
+        }
+        // Closed loops without any junction have no node yet, so pick one of their tiles.
+        for (index, tile) in map.indexed_tiles() {
+            if tile.variant().is_road()
+                && !graph.edges_by_tile.contains_key(&index)
+                && Direction::CARDINALS.iter().any(|d| map.road_connects(index, *d))
+            {
+                let node = graph.add_node(map, index);
+                graph.trace_edges(map, node);
+            }
+        }
+        graph.compute_turns();
+        graph
+    }
+
+    fn add_node(&mut self, map: &Map, tile: (usize, usize)) -> NodeId {
+        let id = self.nodes.len();
+        self.nodes.push(RoadNode {
+            tile,
+            pos: map.tile_center(tile),
+            incoming: Vec::new(),
+            outgoing: Vec::new(),
+        });
+        self.node_by_tile.insert(tile, id);
+        id
+    }
+
+    /// Follows every road leaving `node` until it reaches the next node.
+    fn trace_edges(&mut self, map: &Map, node: NodeId) {
+        let start = self.nodes[node].tile;
+        for direction in Direction::CARDINALS {
+            if !map.road_connects(start, direction) {
+                continue;
+            }
+            let mut tiles = vec![start];
+            let mut heading = direction;
+            let mut current = map.neighbour(start, direction).unwrap();
+            let to = loop {
+                tiles.push(current);
+                if let Some(to) = self.node_by_tile.get(&current) {
+                    break *to;
+                }
+                // Not a node, so there is exactly one way out that isn't the way we came from.
+                heading = Direction::CARDINALS
+                    .into_iter()
+                    .find(|d| *d != heading.opposite() && map.road_connects(current, *d))
+                    .unwrap();
+                current = map.neighbour(current, heading).unwrap();
+            };
+            self.add_edge(map, node, to, tiles, direction, heading);
+        }
+    }
+
+    fn add_edge(
+        &mut self,
+        map: &Map,
+        from: NodeId,
+        to: NodeId,
+        tiles: Vec<(usize, usize)>,
+        start_direction: Direction,
+        end_direction: Direction,
+    ) {
+        let centers: Vec<Vec2> = tiles.iter().map(|t| map.tile_center(*t)).collect();
+        let path: Vec<Vec2> = (0..centers.len())
+            .map(|i| {
+                let heading_in = (i > 0).then(|| (centers[i] - centers[i - 1]).normalize());
+                let heading_out = centers.get(i + 1).map(|next| (*next - centers[i]).normalize());
+                let offset = match (heading_in, heading_out) {
+                    (Some(h_in), Some(h_out)) if h_in.dot(h_out) < 0.5 => right_of(h_in) + right_of(h_out),
+                    (Some(heading), _) | (None, Some(heading)) => right_of(heading),
+                    (None, None) => Vec2::ZERO,
+                };
+                centers[i] + offset * LANE_OFFSET
+            })
+            .collect();
+        let length = path.windows(2).map(|w| w[0].distance(w[1])).sum();
+
+        let id = self.edges.len();
+        for tile in &tiles {
+            self.edges_by_tile.entry(*tile).or_default().push(id);
+        }
+        self.nodes[from].outgoing.push(id);
+        self.nodes[to].incoming.push(id);
+        self.edges.push(RoadEdge {
+            from,
+            to,
+            tiles,
+            path,
+            length,
+            start_direction,
+            end_direction,
+            turns: Vec::new(),
+        });
+    }
+
+    /// U-turns are only permitted at dead ends, every other exit of a node may be taken.
+    fn compute_turns(&mut self) {
+        for id in 0..self.edges.len() {
+            let edge = &self.edges[id];
+            let outgoing = &self.nodes[edge.to].outgoing;
+            let mut turns: Vec<Turn> = outgoing
+                .iter()
+                .map(|out| Turn {
+                    to: *out,
+                    kind: TurnKind::between(edge.end_direction, self.edges[*out].start_direction),
+                })
+                .collect();
+            if turns.iter().any(|t| t.kind != TurnKind::UTurn) {
+                turns.retain(|t| t.kind != TurnKind::UTurn);
+            }
+            self.edges[id].turns = turns;
+        }
+    }
+
+    pub fn node(&self, id: NodeId) -> &RoadNode {
+        &self.nodes[id]
+    }
+
+    pub fn edge(&self, id: EdgeId) -> &RoadEdge {
+        &self.edges[id]
+    }
+
+    /// The intersection covering `world_pos`, if there is one.
+    pub fn node_at(&self, map: &Map, world_pos: Vec2) -> Option<NodeId> {
+        self.node_by_tile.get(&map.to_tile_index_pos(world_pos)).copied()
+    }
+
+    /// Edges that run over the tile at `world_pos`.
+    pub fn edges_at(&self, map: &Map, world_pos: Vec2) -> &[EdgeId] {
+        self.edges_by_tile
+            .get(&map.to_tile_index_pos(world_pos))
+            .map(|edges| edges.as_slice())
+            .unwrap_or(&[])
+    }
+
+    /// The road tile nearest to `world_pos`, searching outward from its tile one ring at a time.
+    pub fn nearest_road_tile(&self, map: &Map, world_pos: Vec2) -> Option<(usize, usize)> {
+        let (x, y) = map.to_tile_index_pos(world_pos);
+        let (x, y) = (x as isize, y as isize);
+        for radius in 0..=map.world_size() as isize {
+            let ring = (-radius..=radius).flat_map(|dy| {
+                (-radius..=radius)
+                    .filter(move |dx| dx.abs() == radius || dy.abs() == radius)
+                    .map(move |dx| (x + dx, y + dy))
+            });
+            let nearest = ring
+                .filter(|(x, y)| *x >= 0 && *y >= 0)
+                .map(|(x, y)| (x as usize, y as usize))
+                .filter(|tile| self.edges_by_tile.contains_key(tile))
+                .min_by(|a, b| {
+                    map.tile_center(*a)
+                        .distance_squared(world_pos)
+                        .total_cmp(&map.tile_center(*b).distance_squared(world_pos))
+                });
+            if nearest.is_some() {
+                return nearest;
+            }
+        }
+        None
+    }
+
+    /// Like [`RoadGraph::nearest_lane`], but off the road it looks on the nearest road tile instead.
+    /// Lanes against `heading` are only taken when there is nothing else there.
+    pub fn snap_to_lane(&self, map: &Map, world_pos: Vec2, heading: Option<Vec2>) -> Option<LanePoint> {
+        let tile = self.nearest_road_tile(map, world_pos)?;
+        let lanes = &self.edges_by_tile[&tile];
+        let nearest = |heading: Option<Vec2>| {
+            lanes
+                .iter()
+                .filter_map(|id| self.nearest_lane_on(*id, world_pos))
+                .filter(|point| heading.is_none_or(|heading| point.direction.dot(heading) >= 0.))
+                .min_by(|a, b| {
+                    a.pos
+                        .distance_squared(world_pos)
+                        .total_cmp(&b.pos.distance_squared(world_pos))
+                })
+        };
+        nearest(heading).or_else(|| nearest(None))
+    }
+
+    /// Closest point to `world_pos` on the lane of `edge`.
+    pub fn nearest_lane_on(&self, edge: EdgeId, world_pos: Vec2) -> Option<LanePoint> {
+        self.edges.get(edge).map(|e| e.project(edge, world_pos))
+    }
+
+    /// Closest lane point on the tile at `world_pos`.
+    /// If `heading` is given, lanes running against it are skipped.
+    pub fn nearest_lane(&self, map: &Map, world_pos: Vec2, heading: Option<Vec2>) -> Option<LanePoint> {
+        self.edges_at(map, world_pos)
+            .iter()
+            .filter_map(|id| self.nearest_lane_on(*id, world_pos))
+            .filter(|point| heading.is_none_or(|heading| point.direction.dot(heading) >= 0.))
+            .min_by(|a, b| {
+                a.pos
+                    .distance_squared(world_pos)
+                    .total_cmp(&b.pos.distance_squared(world_pos))
+            })
+    }
+}
+
+impl RoadEdge {
+    /// Position and direction of travel `distance` units along the lane.
+    pub fn point_at(&self, distance: f32) -> (Vec2, Vec2) {
+        let mut remaining = distance.max(0.);
+        for w in self.path.windows(2) {
+            let segment_length = w[0].distance(w[1]);
+            let direction = (w[1] - w[0]).normalize_or_zero();
+            if remaining <= segment_length {
+                return (w[0] + direction * remaining, direction);
+            }
+            remaining -= segment_length;
+        }
+        let last = self.path.len() - 1;
+        let direction = match last {
+            0 => Vec2::ZERO,
+            _ => (self.path[last] - self.path[last - 1]).normalize_or_zero(),
+        };
+        (self.path[last], direction)
+    }
+
+    fn project(&self, id: EdgeId, world_pos: Vec2) -> LanePoint {
+        let mut best = LanePoint {
+            edge: id,
+            distance: 0.,
+            pos: self.path[0],
+            direction: self.point_at(0.).1,
+        };
+        let mut walked = 0.;
+        for w in self.path.windows(2) {
+            let segment = w[1] - w[0];
+            let segment_length = segment.length();
+            let t = ((world_pos - w[0]).dot(segment) / segment_length.powi(2)).clamp(0., 1.);
+            let pos = w[0] + segment * t;
+            if pos.distance_squared(world_pos) < best.pos.distance_squared(world_pos) {
+                best = LanePoint {
+                    edge: id,
+                    distance: walked + segment_length * t,
+                    pos,
+                    direction: segment / segment_length,
+                };
+            }
+            walked += segment_length;
+        }
+        best
+    }
+}
+
+/// Right hand side of a heading in screen space (y pointing down).
+fn right_of(heading: Vec2) -> Vec2 {
+    Vec2::new(-heading.y, heading.x)
+}
@@ -0,0 +1,286 @@
+// `DeJson` reads the optional `PlayerSave::in_vehicle` with a match clippy wants written with `?`.
+// The generated impl is its own item, so an allow on the struct wouldn't reach it.
+#![allow(clippy::question_mark)]
+
+use std::{fs, path::PathBuf, rc::Rc};
+
+use macroquad::prelude::*;
+use nanoserde::{DeJson, SerJson};
+
+use crate::{
+    controller::ControlScheme,
+    draw::Draw,
+    game::Sprites,
+    map::{Map, MapSource},
+    player::Player,
+    script::Scripts,
+    vehicle::Seat,
+    weapons::{Weapon, WeaponKind},
+    World,
+};
+
+/// Version of the save format written by this build. Bump it whenever the format changes
+/// and teach `migrate` how to upgrade the previous version.
+pub const SAVE_VERSION: u32 = 3;
+pub const SAVE_SLOTS: u8 = 3;
+const SAVE_DIR: &str = "saves";
+
+/// Everything needed to resume a session.
+#[derive(Debug, Clone, SerJson, DeJson)]
+pub struct SaveGame {
+    pub version: u32,
+    pub map: MapSource,
+    pub player: PlayerSave,
+    /// Vehicles the player has driven.
+    pub vehicles: Vec<VehicleSave>,
+    pub wanted_stars: u8,
+    /// Missing before version 3, the scripts start over then.
+    #[nserde(default)]
+    pub scripts: Vec<ScriptSave>,
+}
+
+#[derive(Debug, Clone, SerJson, DeJson)]
+pub struct PlayerSave {
+    pub pos: [f32; 2],
+    pub health: f32,
+    #[nserde(default)]
+    pub armor: f32,
+    pub money: u32,
+    pub weapons: Vec<WeaponSave>,
+    pub current_weapon: usize,
+    /// Index into the saved vehicles of the one the player is sitting in.
+    pub in_vehicle: Option<usize>,
+    /// The passenger seat the player sits in, if not the driver's.
+    #[nserde(default)]
+    pub passenger_seat: Option<usize>,
+    pub completed_missions: Vec<String>,
+    #[nserde(default)]
+    pub control_scheme: ControlScheme,
+}
+
+#[derive(Debug, Clone, SerJson, DeJson)]
+pub struct WeaponSave {
+    pub kind: WeaponKind,
+    pub ammo: u32,
+}
+
+#[derive(Debug, Clone, SerJson, DeJson)]
+pub struct VehicleSave {
+    /// Missing from version 1 saves, filled in when they are migrated.
+    #[nserde(default)]
+    pub model: String,
+    pub pos: [f32; 2],
+    pub rotation: [f32; 2],
+    pub damage: f32,
+    pub locked: bool,
+}
+
+/// What a script kept in `this`, as JSON.
+#[derive(Debug, Clone, SerJson, DeJson)]
+pub struct ScriptSave {
+    pub name: String,
+    pub state: String,
+}
+
+/// Just enough of a save to tell which format the rest of it is in.
+#[derive(DeJson)]
+struct SaveHeader {
+    version: u32,
+}
+
+impl SaveGame {
+    /// Takes a snapshot of the session. Fails for maps that can't be rebuilt when loading.
+    pub fn capture(world: &World, player: &Player, scripts: &Scripts) -> Result<Self, String> {
+        if world.map.source == MapSource::Custom {
+            return Err("Can't save a game on a map that wasn't generated or loaded from a file".to_string());
+        }
+        let owned: Vec<_> = world.vehicles.iter().filter(|v| v.borrow().owned).collect();
+        let in_vehicle = player
+            .in_vehicle
+            .as_ref()
+            .and_then(|vehicle| owned.iter().position(|v| Rc::ptr_eq(v, vehicle)));
+        Ok(SaveGame {
+            version: SAVE_VERSION,
+            map: world.map.source.clone(),
+            player: PlayerSave {
+                pos: player.pos.into(),
+                health: player.health,
+                armor: player.armor,
+                money: player.money,
+                weapons: player
+                    .weapons
+                    .iter()
+                    .map(|w| WeaponSave {
+                        kind: w.kind,
+                        ammo: w.ammo,
+                    })
+                    .collect(),
+                current_weapon: player.current_weapon,
+                in_vehicle,
+                passenger_seat: match player.seat {
+                    Seat::Passenger(i) if in_vehicle.is_some() => Some(i),
+                    _ => None,
+                },
+                completed_missions: player.completed_missions.clone(),
+                control_scheme: player.control_scheme,
+            },
+            vehicles: owned
+                .iter()
+                .map(|v| {
+                    let v = v.borrow();
+                    VehicleSave {
+                        model: v.model.clone(),
+                        pos: v.position().into(),
+                        rotation: v.rotation.into(),
+                        damage: v.damage,
+                        locked: v.locked,
+                    }
+                })
+                .collect(),
+            wanted_stars: world.wanted.stars(),
+            scripts: scripts.save(),
+        })
+    }
+
+    /// Rebuilds the world from the save and puts `player` back where they were.
+    /// The player is only touched once the world could be built.
+    pub fn restore(&self, sprites: &Sprites, seed: u64, player: &mut Player) -> Result<World, String> {
+        let mut world = World::new(Map::from_source(sprites.tile_map.clone(), &self.map)?, seed);
+        let vehicles = self
+            .vehicles
+            .iter()
+            .map(|saved| {
+                let mut vehicle = sprites.vehicles.spawn(&saved.model)?;
+                vehicle.set_position(saved.pos.into());
+                vehicle.rotation = Vec2::from(saved.rotation).normalize_or(Vec2::NEG_Y);
+                vehicle.damage = saved.damage;
+                vehicle.locked = saved.locked;
+                vehicle.owned = true;
+                Ok(world.add_vehicle(vehicle))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        world.wanted.set_stars(self.wanted_stars);
+
+        let saved = &self.player;
+        player.pos = saved.pos.into();
+        player.health = saved.health;
+        player.armor = saved.armor;
+        player.money = saved.money;
+        player.weapons = saved
+            .weapons
+            .iter()
+            .map(|w| {
+                let mut weapon = Weapon::new(w.kind, sprites.weapon.clone());
+                weapon.ammo = w.ammo;
+                weapon
+            })
+            .collect();
+        player.current_weapon = saved.current_weapon;
+        player.completed_missions = saved.completed_missions.clone();
+        player.control_scheme = saved.control_scheme;
+        player.entering = None;
+        player.in_vehicle = None;
+        if let Some(vehicle) = saved.in_vehicle.and_then(|i| vehicles.get(i)) {
+            let seat = saved.passenger_seat.map_or(Seat::Driver, Seat::Passenger);
+            player.enter_vehicle(vehicle.clone(), seat);
+        }
+        Ok(world)
+    }
+
+    pub fn to_json(&self) -> String {
+        self.serialize_json()
+    }
+
+    /// Parses a save of any supported version, upgrading it to the current format.
+    pub fn from_json(text: &str) -> Result<Self, String> {
+        let header = SaveHeader::deserialize_json(text).map_err(|e| format!("Not a save game: {e}"))?;
+        let mut save = migrate(header.version, text)?;
+        save.version = SAVE_VERSION;
+        Ok(save)
+    }
+}
+
+/// Reads a save written in format `version` and brings it up to date.
+/// Older formats are upgraded one version at a time until they reach `SAVE_VERSION`.
+fn migrate(version: u32, text: &str) -> Result<SaveGame, String> {
+    match version {
+        SAVE_VERSION => SaveGame::deserialize_json(text).map_err(|e| format!("Corrupt save game: {e}")),
+        1 => {
+            // Version 1 had only one kind of car, so every saved vehicle was a sedan
+            let mut save = migrate(2, text)?;
+            for vehicle in &mut save.vehicles {
+                vehicle.model = "sedan".to_string();
+            }
+            Ok(save)
+        }
+        // Version 2 didn't keep what the scripts had in `this`, they start over
+        2 => migrate(3, text),
+        version if version > SAVE_VERSION => Err(format!(
+            "Save game format {version} is from a newer version of the game"
+        )),
+        version => Err(format!("Unsupported save game format {version}")),
+    }
+}
+
+pub fn slot_path(slot: u8) -> PathBuf {
+    PathBuf::from(SAVE_DIR).join(format!("slot{slot}.json"))
+}
+
+pub fn save_to_slot(slot: u8, save: &SaveGame) -> Result<(), String> {
+    fs::create_dir_all(SAVE_DIR).map_err(|e| format!("Failed to create {SAVE_DIR}: {e}"))?;
+    let path = slot_path(slot);
+    fs::write(&path, save.to_json()).map_err(|e| format!("Failed to write {}: {e}", path.display()))
+}
+
+pub fn load_from_slot(slot: u8) -> Result<SaveGame, String> {
+    let path = slot_path(slot);
+    let text = fs::read_to_string(&path).map_err(|e| format!("Failed to read {}: {e}", path.display()))?;
+    SaveGame::from_json(&text)
+}
+
+/// Slots that hold a save game.
+pub fn used_slots() -> Vec<u8> {
+    (0..SAVE_SLOTS).filter(|slot| slot_path(*slot).exists()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn migrates_version_1() {
+        let text = r#"{"version":1,"map":{"City":{"size":64,"block_size":4}},"player":{"pos":[10,-20],"health":80,"money":500,"weapons":[{"kind":"Pistol","ammo":12}],"current_weapon":0,"in_vehicle":0,"completed_missions":["first_wheels"]},"vehicles":[{"pos":[0,0],"rotation":[0,-1],"damage":35,"locked":false}],"wanted_stars":2}"#;
+        let save = SaveGame::from_json(text).unwrap();
+        assert_eq!(save.version, SAVE_VERSION);
+        assert_eq!(save.map, MapSource::City { size: 64, block_size: 4 });
+        assert_eq!(save.player.pos, [10., -20.]);
+        assert_eq!(save.player.money, 500);
+        assert_eq!(save.player.weapons[0].ammo, 12);
+        assert_eq!(save.player.in_vehicle, Some(0));
+        assert_eq!(save.player.control_scheme, ControlScheme::Tank);
+        assert_eq!(save.player.completed_missions, ["first_wheels"]);
+        assert_eq!(save.vehicles[0].model, "sedan");
+        assert_eq!(save.vehicles[0].damage, 35.);
+        assert_eq!(save.wanted_stars, 2);
+        assert!(save.scripts.is_empty());
+    }
+
+    #[test]
+    fn migrates_version_2() {
+        let text = r#"{"version":2,"map":{"Crossings":{"size":32}},"player":{"pos":[0,0],"health":100,"money":0,"weapons":[],"current_weapon":0,"in_vehicle":null,"completed_missions":[],"control_scheme":"ScreenRelative"},"vehicles":[{"model":"taxi","pos":[512,0],"rotation":[1,0],"damage":0,"locked":true}],"wanted_stars":0}"#;
+        let save = SaveGame::from_json(text).unwrap();
+        assert_eq!(save.version, SAVE_VERSION);
+        assert_eq!(save.map, MapSource::Crossings { size: 32 });
+        assert_eq!(save.player.in_vehicle, None);
+        assert_eq!(save.player.control_scheme, ControlScheme::ScreenRelative);
+        assert_eq!(save.vehicles[0].model, "taxi");
+        assert!(save.vehicles[0].locked);
+        assert!(save.scripts.is_empty());
+    }
+
+    #[test]
+    fn rejects_saves_from_newer_versions() {
+        let text = format!(r#"{{"version":{}}}"#, SAVE_VERSION + 1);
+        assert!(SaveGame::from_json(&text).is_err());
+    }
+}
@@ -0,0 +1,457 @@
+use std::{cell::RefCell, fs, path::PathBuf, rc::Rc};
+
+use macroquad::prelude::*;
+use rhai::{CallFnOptions, Dynamic, Engine, EvalAltResult, Map, Scope, AST};
+
+use crate::{
+    collide::Shape,
+    draw::Draw,
+    event::{Entity, GameEvent},
+    minimap::{Blip, BlipKind},
+    pedestrian::Pedestrian,
+    player::{Player, MAX_ARMOR, MAX_HEALTH},
+    save::ScriptSave,
+    trigger::{Detects, ZoneEvent, ZoneEventKind},
+    vehicle::{catalogue::VehicleCatalogue, Vehicle},
+    weapons::{Weapon, WeaponKind},
+    World,
+};
+
+/// A single hook running longer than this many steps is stopped, so a broken script can't hang the game.
+const MAX_OPERATIONS: u64 = 200_000;
+const MAX_CALL_LEVELS: usize = 32;
+/// Rhai's defaults are much lower in debug builds, this way scripts that work in one build work in the other.
+const MAX_EXPR_DEPTH: usize = 64;
+const MAX_FUNCTION_EXPR_DEPTH: usize = 32;
+
+/// Something a script asked for, done to the world once the script returns.
+enum Command {
+    Teleport(Vec2),
+    SetHealth(f32),
+    SetArmor(f32),
+    GiveMoney(i64),
+    GiveAmmo(u32),
+    GiveWeapon(WeaponKind),
+    SetWanted(u8),
+    AddVehicle(Rc<RefCell<Vehicle>>),
+    SpawnPedestrian(Vec2),
+    RemoveVehicle(Rc<RefCell<Vehicle>>),
+    LockVehicle(Rc<RefCell<Vehicle>>, bool),
+    RepairVehicle(Rc<RefCell<Vehicle>>),
+    MoveVehicle(Rc<RefCell<Vehicle>>, Vec2),
+    TurnVehicle(Rc<RefCell<Vehicle>>, Vec2),
+    AddZone(String, Shape),
+    RemoveZone(String),
+    AddBlip(Blip),
+    RemoveBlip(String),
+}
+
+/// Where the commands of every hook go. Vehicles scripts hold on to between hooks queue theirs here too.
+type CommandQueue = Rc<RefCell<Vec<Command>>>;
+
+/// What scripts see of the game during one hook.
+/// Player and world are read from a copy taken before the hook runs, changes go through `Command`s.
+struct ScriptState {
+    player_pos: Vec2,
+    player_health: f32,
+    money: u32,
+    stars: u8,
+    player_vehicle: Option<Rc<RefCell<Vehicle>>>,
+    vehicles: Vec<Rc<RefCell<Vehicle>>>,
+    catalogue: VehicleCatalogue,
+    commands: CommandQueue,
+}
+
+/// The `game` passed to every hook.
+#[derive(Clone)]
+struct ScriptGame(Rc<RefCell<ScriptState>>);
+
+impl ScriptGame {
+    fn push(&mut self, command: Command) {
+        self.0.borrow().commands.borrow_mut().push(command);
+    }
+
+    fn vehicle(&self, vehicle: Rc<RefCell<Vehicle>>) -> ScriptVehicle {
+        ScriptVehicle {
+            vehicle,
+            commands: self.0.borrow().commands.clone(),
+        }
+    }
+
+    fn spawn_vehicle(&mut self, model: &str, x: f32, y: f32) -> Result<ScriptVehicle, Box<EvalAltResult>> {
+        let mut vehicle = self.0.borrow().catalogue.spawn(model)?;
+        vehicle.set_position(Vec2::new(x, y));
+        let vehicle = Rc::new(RefCell::new(vehicle));
+        self.push(Command::AddVehicle(vehicle.clone()));
+        Ok(self.vehicle(vehicle))
+    }
+
+    /// Hands the player a weapon and switches to it. `kind` is one of `"pistol"`, `"smg"` or `"shotgun"`.
+    fn give_weapon(&mut self, kind: &str) -> Result<(), Box<EvalAltResult>> {
+        let kind = WeaponKind::from_name(kind).ok_or_else(|| format!("no weapon called {kind}"))?;
+        self.push(Command::GiveWeapon(kind));
+        Ok(())
+    }
+
+    /// `corners` is an array of `[x, y]` arrays.
+    fn add_zone_polygon(&mut self, name: &str, corners: rhai::Array) -> Result<(), Box<EvalAltResult>> {
+        let corners = corners
+            .into_iter()
+            .map(|corner| match corner.into_typed_array::<f32>().as_deref() {
+                Ok([x, y]) => Ok(Vec2::new(*x, *y)),
+                _ => Err("polygon corners must be [x, y]"),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if corners.len() < 3 {
+            return Err("a polygon needs at least 3 corners".into());
+        }
+        self.push(Command::AddZone(name.to_string(), Shape::Polygon(corners)));
+        Ok(())
+    }
+
+    /// `kind` is one of "mission", "objective", "police", "shop" or "waypoint".
+    fn add_blip(&mut self, name: &str, kind: &str, x: f32, y: f32) -> Result<(), Box<EvalAltResult>> {
+        let kind = BlipKind::from_name(kind).ok_or_else(|| format!("unknown blip kind {kind}"))?;
+        self.push(Command::AddBlip(Blip {
+            name: name.to_string(),
+            kind,
+            pos: Vec2::new(x, y),
+        }));
+        Ok(())
+    }
+}
+
+/// A vehicle handed to scripts. Stays valid after the vehicle was despawned, it is just not in the world anymore.
+/// Changes to it are queued like those to the game, and done once the hook returns.
+#[derive(Clone)]
+struct ScriptVehicle {
+    vehicle: Rc<RefCell<Vehicle>>,
+    commands: CommandQueue,
+}
+
+impl ScriptVehicle {
+    fn push(&mut self, command: Command) {
+        self.commands.borrow_mut().push(command);
+    }
+}
+
+/// A loaded script and what it keeps between hooks in `this`.
+struct Script {
+    name: String,
+    ast: AST,
+    state: Dynamic,
+}
+
+impl Script {
+    fn has_hook(&self, hook: &str) -> bool {
+        self.ast.iter_functions().any(|f| f.name == hook)
+    }
+}
+
+/// Scripts run alongside the game, without having to recompile it.
+/// Each can define the hooks `on_start(game)`, `on_tick(game, dt)`, `on_vehicle_entered(game, vehicle)`,
+/// `on_zone_entered(game, zone, entity)`, `on_zone_exited(game, zone, entity)` and `on_mission_completed(game, id)`,
+/// and keep its own state in `this`, which is saved with the game.
+pub struct Scripts {
+    engine: Engine,
+    scripts: Vec<Script>,
+    started: bool,
+    commands: CommandQueue,
+}
+
+impl Default for Scripts {
+    fn default() -> Self {
+        Scripts {
+            engine: new_engine(),
+            scripts: Vec::new(),
+            started: false,
+            commands: CommandQueue::default(),
+        }
+    }
+}
+
+impl Scripts {
+    /// Compiles every script in `dir`, in file name order. A missing directory means no scripts.
+    pub fn load(dir: &str) -> Result<Self, String> {
+        let mut scripts = Scripts::default();
+        let Ok(entries) = fs::read_dir(dir) else {
+            return Ok(scripts);
+        };
+        let mut paths: Vec<PathBuf> = entries
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == "rhai"))
+            .collect();
+        paths.sort();
+        for path in paths {
+            let text = fs::read_to_string(&path).map_err(|e| format!("Failed to read {}: {e}", path.display()))?;
+            let ast = scripts
+                .engine
+                .compile(&text)
+                .map_err(|e| format!("Failed to compile {}: {e}", path.display()))?;
+            scripts.scripts.push(Script {
+                name: path.display().to_string(),
+                ast,
+                state: Dynamic::from_map(Default::default()),
+            });
+        }
+        Ok(scripts)
+    }
+
+    /// What every script keeps in `this`, for saving the game.
+    pub fn save(&self) -> Vec<ScriptSave> {
+        self.scripts
+            .iter()
+            .filter_map(|script| {
+                let state = script.state.read_lock::<Map>()?;
+                Some(ScriptSave {
+                    name: script.name.clone(),
+                    state: rhai::format_map_as_json(&state),
+                })
+            })
+            .collect()
+    }
+
+    /// Gives the scripts back what they kept in `this` when the game was saved.
+    /// `on_start` still runs afterwards, so it has to set up the world from that state instead of starting over.
+    pub fn restore(&mut self, saved: &[ScriptSave]) -> Result<(), String> {
+        for save in saved {
+            let Some(script) = self.scripts.iter_mut().find(|s| s.name == save.name) else {
+                println!("script {} is gone, dropping what it saved", save.name);
+                continue;
+            };
+            let state = self
+                .engine
+                .parse_json(&save.state, true)
+                .map_err(|e| format!("Corrupt state of script {}: {e}", save.name))?;
+            script.state = Dynamic::from_map(state);
+        }
+        Ok(())
+    }
+
+    /// Runs `on_start` the first time, then `on_tick`.
+    pub fn update(&mut self, world: &mut World, player: &mut Player, dt: f32) {
+        if !self.started {
+            self.started = true;
+            self.call(world, player, "on_start", |game| (game,));
+        }
+        self.call(world, player, "on_tick", |game| (game, dt));
+    }
+
+    fn vehicle(&self, vehicle: Rc<RefCell<Vehicle>>) -> ScriptVehicle {
+        ScriptVehicle {
+            vehicle,
+            commands: self.commands.clone(),
+        }
+    }
+
+    /// Runs the hooks for `event`, if it has any.
+    pub fn handle_event(&mut self, world: &mut World, player: &mut Player, event: &GameEvent) {
+        match event {
+            GameEvent::VehicleEntered { vehicle, .. } => {
+                let vehicle = self.vehicle(vehicle.clone());
+                self.call(world, player, "on_vehicle_entered", |game| (game, vehicle.clone()));
+            }
+            GameEvent::MissionCompleted { id, .. } => {
+                self.call(world, player, "on_mission_completed", |game| (game, id.clone()));
+            }
+            GameEvent::Zone(event) => self.zone_event(world, player, event),
+            _ => {}
+        }
+    }
+
+    /// Runs `on_zone_entered` or `on_zone_exited` with the name of the zone and who it was:
+    /// `"player"`, `"pedestrian"` or the vehicle.
+    fn zone_event(&mut self, world: &mut World, player: &mut Player, event: &ZoneEvent) {
+        let hook = match event.kind {
+            ZoneEventKind::Enter => "on_zone_entered",
+            ZoneEventKind::Exit => "on_zone_exited",
+            ZoneEventKind::Stay => return,
+        };
+        let Some(zone) = world.triggers.zone(event.zone).map(|zone| zone.name.clone()) else {
+            return;
+        };
+        let entity = match &event.entity {
+            Entity::Player => Dynamic::from("player"),
+            Entity::Pedestrian(_) => Dynamic::from("pedestrian"),
+            Entity::Vehicle(vehicle) => Dynamic::from(self.vehicle(vehicle.clone())),
+        };
+        self.call(world, player, hook, |game| (game, zone.clone(), entity.clone()));
+    }
+
+    /// Calls `hook` of every script defining it with the arguments `args` builds from the `game`,
+    /// then carries out what the scripts asked for. Script errors are reported and otherwise ignored.
+    fn call<A: rhai::FuncArgs>(
+        &mut self,
+        world: &mut World,
+        player: &mut Player,
+        hook: &str,
+        args: impl Fn(ScriptGame) -> A,
+    ) {
+        if !self.scripts.iter().any(|s| s.has_hook(hook)) {
+            return;
+        }
+        let game = ScriptGame(Rc::new(RefCell::new(ScriptState {
+            player_pos: player.pos,
+            player_health: player.health,
+            money: player.money,
+            stars: world.wanted.stars(),
+            player_vehicle: player.in_vehicle.clone(),
+            vehicles: world.vehicles.clone(),
+            catalogue: std::mem::take(&mut world.vehicle_catalogue),
+            commands: self.commands.clone(),
+        })));
+        for script in self.scripts.iter_mut().filter(|s| s.has_hook(hook)) {
+            let options = CallFnOptions::new().eval_ast(false).bind_this_ptr(&mut script.state);
+            let result =
+                self.engine
+                    .call_fn_with_options::<Dynamic>(options, &mut Scope::new(), &script.ast, hook, args(game.clone()));
+            if let Err(e) = result {
+                println!("{} {hook}: {e}", script.name);
+            }
+        }
+        world.vehicle_catalogue = std::mem::take(&mut game.0.borrow_mut().catalogue);
+        for command in self.commands.take() {
+            apply(command, world, player);
+        }
+    }
+}
+
+fn apply(command: Command, world: &mut World, player: &mut Player) {
+    match command {
+        Command::Teleport(pos) => match &player.in_vehicle {
+            Some(vehicle) => vehicle.borrow_mut().set_position(pos),
+            None => player.pos = pos,
+        },
+        Command::SetHealth(health) => player.health = health.clamp(0., MAX_HEALTH),
+        Command::SetArmor(armor) => player.armor = armor.clamp(0., MAX_ARMOR),
+        Command::GiveMoney(amount) => player.money = (player.money as i64 + amount).clamp(0, u32::MAX as i64) as u32,
+        Command::GiveAmmo(ammo) => {
+            if let Some(weapon) = player.weapon_mut() {
+                weapon.ammo = weapon.ammo.saturating_add(ammo);
+            }
+        }
+        Command::GiveWeapon(kind) => {
+            // Another one of a weapon the player already has only adds its rounds
+            if let Some(index) = player.weapons.iter().position(|w| w.kind == kind) {
+                let weapon = &mut player.weapons[index];
+                weapon.ammo = weapon.ammo.saturating_add(kind.starting_ammo());
+                player.current_weapon = index;
+            } else if let Some(texture) = world.weapon_sprite.clone() {
+                player.weapons.push(Weapon::new(kind, texture));
+                player.current_weapon = player.weapons.len() - 1;
+            }
+        }
+        Command::SetWanted(stars) => world.set_wanted_stars(stars),
+        Command::AddVehicle(vehicle) => world.insert_vehicle(vehicle),
+        Command::SpawnPedestrian(pos) => {
+            if let Some(sprite) = world.crowd.sprites.first().cloned() {
+                world.crowd.add(Pedestrian::new(sprite, pos));
+            }
+        }
+        Command::AddZone(name, shape) => {
+            world.triggers.add(&name, shape, Detects::ALL);
+        }
+        Command::RemoveZone(name) => world.triggers.remove_named(&name),
+        Command::LockVehicle(vehicle, locked) => vehicle.borrow_mut().locked = locked,
+        Command::RepairVehicle(vehicle) => vehicle.borrow_mut().damage = 0.,
+        Command::MoveVehicle(vehicle, pos) => vehicle.borrow_mut().set_position(pos),
+        Command::TurnVehicle(vehicle, direction) => vehicle.borrow_mut().rotation = direction.normalize_or(Vec2::NEG_Y),
+        Command::AddBlip(blip) => world.blips.push(blip),
+        Command::RemoveBlip(name) => world.blips.retain(|blip| blip.name != name),
+        Command::RemoveVehicle(vehicle) => {
+            let occupied_by_player = player.in_vehicle.as_ref().is_some_and(|v| Rc::ptr_eq(v, &vehicle));
+            if !occupied_by_player {
+                world.traffic.remove_driver(&vehicle);
+                world.remove_vehicle(&vehicle);
+            }
+        }
+    }
+}
+
+/// The engine with the game's API registered and limits for misbehaving scripts.
+fn new_engine() -> Engine {
+    let mut engine = Engine::new();
+    engine
+        .set_max_operations(MAX_OPERATIONS)
+        .set_max_call_levels(MAX_CALL_LEVELS)
+        .set_max_expr_depths(MAX_EXPR_DEPTH, MAX_FUNCTION_EXPR_DEPTH)
+        .disable_symbol("eval");
+
+    engine
+        .register_type_with_name::<ScriptGame>("Game")
+        .register_get("player_x", |g: &mut ScriptGame| g.0.borrow().player_pos.x)
+        .register_get("player_y", |g: &mut ScriptGame| g.0.borrow().player_pos.y)
+        .register_get("player_health", |g: &mut ScriptGame| g.0.borrow().player_health)
+        .register_get("money", |g: &mut ScriptGame| g.0.borrow().money as i64)
+        .register_get("wanted_stars", |g: &mut ScriptGame| g.0.borrow().stars as i64)
+        .register_get("player_vehicle", |g: &mut ScriptGame| {
+            g.0.borrow()
+                .player_vehicle
+                .clone()
+                .map_or(Dynamic::UNIT, |v| Dynamic::from(g.vehicle(v)))
+        })
+        .register_get("vehicles", |g: &mut ScriptGame| {
+            g.0.borrow()
+                .vehicles
+                .iter()
+                .map(|v| Dynamic::from(g.vehicle(v.clone())))
+                .collect::<rhai::Array>()
+        })
+        .register_fn("teleport", |g: &mut ScriptGame, x: f32, y: f32| {
+            g.push(Command::Teleport(Vec2::new(x, y)))
+        })
+        .register_fn("set_health", |g: &mut ScriptGame, health: f32| g.push(Command::SetHealth(health)))
+        .register_fn("set_armor", |g: &mut ScriptGame, armor: f32| g.push(Command::SetArmor(armor)))
+        .register_fn("give_money", |g: &mut ScriptGame, amount: i64| g.push(Command::GiveMoney(amount)))
+        .register_fn("give_ammo", |g: &mut ScriptGame, ammo: i64| {
+            g.push(Command::GiveAmmo(ammo.clamp(0, u32::MAX as i64) as u32))
+        })
+        .register_fn("give_weapon", ScriptGame::give_weapon)
+        .register_fn("set_wanted", |g: &mut ScriptGame, stars: i64| {
+            g.push(Command::SetWanted(stars.clamp(0, u8::MAX as i64) as u8))
+        })
+        .register_fn("spawn_vehicle", ScriptGame::spawn_vehicle)
+        .register_fn("spawn_pedestrian", |g: &mut ScriptGame, x: f32, y: f32| {
+            g.push(Command::SpawnPedestrian(Vec2::new(x, y)))
+        })
+        .register_fn("add_zone_circle", |g: &mut ScriptGame, name: &str, x: f32, y: f32, r: f32| {
+            g.push(Command::AddZone(name.to_string(), Shape::Circle(Circle::new(x, y, r))))
+        })
+        .register_fn(
+            "add_zone_rect",
+            |g: &mut ScriptGame, name: &str, x: f32, y: f32, w: f32, h: f32| {
+                g.push(Command::AddZone(name.to_string(), Shape::Rect(Rect::new(x, y, w, h))))
+            },
+        )
+        .register_fn("add_zone_polygon", ScriptGame::add_zone_polygon)
+        .register_fn("remove_zone", |g: &mut ScriptGame, name: &str| {
+            g.push(Command::RemoveZone(name.to_string()))
+        })
+        .register_fn("add_blip", ScriptGame::add_blip)
+        .register_fn("remove_blip", |g: &mut ScriptGame, name: &str| {
+            g.push(Command::RemoveBlip(name.to_string()))
+        })
+        .register_fn("despawn", |g: &mut ScriptGame, v: ScriptVehicle| g.push(Command::RemoveVehicle(v.vehicle)));
+
+    engine
+        .register_type_with_name::<ScriptVehicle>("Vehicle")
+        .register_get("model", |v: &mut ScriptVehicle| v.vehicle.borrow().model.clone())
+        .register_get("x", |v: &mut ScriptVehicle| v.vehicle.borrow().position().x)
+        .register_get("y", |v: &mut ScriptVehicle| v.vehicle.borrow().position().y)
+        .register_get("speed", |v: &mut ScriptVehicle| v.vehicle.borrow().velocity)
+        .register_get("damage", |v: &mut ScriptVehicle| v.vehicle.borrow().damage)
+        .register_get_set(
+            "locked",
+            |v: &mut ScriptVehicle| v.vehicle.borrow().locked,
+            |v: &mut ScriptVehicle, locked: bool| v.push(Command::LockVehicle(v.vehicle.clone(), locked)),
+        )
+        .register_fn("repair", |v: &mut ScriptVehicle| v.push(Command::RepairVehicle(v.vehicle.clone())))
+        .register_fn("set_position", |v: &mut ScriptVehicle, x: f32, y: f32| {
+            v.push(Command::MoveVehicle(v.vehicle.clone(), Vec2::new(x, y)))
+        })
+        .register_fn("set_rotation", |v: &mut ScriptVehicle, x: f32, y: f32| {
+            v.push(Command::TurnVehicle(v.vehicle.clone(), Vec2::new(x, y)))
+        })
+        .register_fn("==", |a: &mut ScriptVehicle, b: ScriptVehicle| Rc::ptr_eq(&a.vehicle, &b.vehicle));
+    engine
+}
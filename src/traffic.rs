@@ -0,0 +1,260 @@
+use std::{cell::RefCell, rc::Rc};
+
+use macroquad::prelude::*;
+
+use crate::{
+    draw::Draw,
+    intersection::{Intersections, Signal},
+    map::{Map, TILE_TEX_SIZE_SCALED},
+    rng::Rng,
+    road::{EdgeId, RoadGraph, LANE_OFFSET},
+    vehicle::{Occupant, Vehicle},
+};
+
+/// Traffic spawns in a ring around the player, just outside of the visible area.
+pub const SPAWN_RADIUS_MIN: f32 = 1200.;
+pub const SPAWN_RADIUS_MAX: f32 = 2500.;
+pub const DESPAWN_RADIUS: f32 = 3500.;
+pub const MAX_TRAFFIC_VEHICLES: usize = 12;
+const SPAWN_ATTEMPTS_PER_FRAME: usize = 4;
+/// Minimum distance to any other vehicle for a spawn point to be used.
+const SPAWN_CLEARANCE: f32 = 600.;
+
+pub const CRUISE_SPEED: f32 = 8.;
+pub const TURN_SPEED: f32 = 3.;
+/// How far ahead on the lane drivers aim when steering.
+const LOOKAHEAD: f32 = 300.;
+/// Bumper to bumper gap drivers try to keep.
+const FOLLOWING_DISTANCE: f32 = 150.;
+/// Chance of each passenger seat of a new traffic vehicle being taken.
+const PASSENGER_CHANCE: f32 = 0.25;
+/// Speed reduction per unit of gap missing to the following distance.
+const FOLLOWING_GAIN: f32 = 0.03;
+/// Obstacles further than this to the side of the car are not in its way.
+const LANE_HALF_WIDTH: f32 = LANE_OFFSET;
+const SPEED_TOLERANCE: f32 = 0.5;
+/// Drivers closer than this to the end of their lane when the lights turn amber keep going.
+const AMBER_STOP_DISTANCE: f32 = 700.;
+
+/// An AI driver steering one vehicle along the lane graph.
+pub struct TrafficDriver {
+    pub vehicle: Rc<RefCell<Vehicle>>,
+    pub edge: EdgeId,
+    pub next_edge: Option<EdgeId>,
+    /// Progress along `edge`.
+    pub distance: f32,
+}
+
+impl TrafficDriver {
+    pub fn new(vehicle: Rc<RefCell<Vehicle>>, roads: &RoadGraph, edge: EdgeId, distance: f32, rng: &mut Rng) -> Self {
+        {
+            let mut vehicle = vehicle.borrow_mut();
+            vehicle.driver = Some(Occupant::Npc);
+            for seat in vehicle.passengers.iter_mut() {
+                if rng.chance(PASSENGER_CHANCE) {
+                    *seat = Some(Occupant::Npc);
+                }
+            }
+        }
+        TrafficDriver {
+            vehicle,
+            edge,
+            next_edge: pick_turn(roads, edge, rng),
+            distance,
+        }
+    }
+
+    /// Sets throttle and steering of the vehicle for this frame,
+    /// the same inputs `Vehicle::handle_controls` produces for the player.
+    /// `obstacles` are the positions of everything the driver has to keep its distance to.
+    pub fn drive(&mut self, roads: &RoadGraph, intersections: &Intersections, obstacles: &[Vec2], rng: &mut Rng) {
+        let vehicle = self.vehicle.clone();
+        let mut vehicle = vehicle.borrow_mut();
+        self.advance(roads, vehicle.position(), rng);
+        let target = self.lane_point_ahead(roads, LOOKAHEAD);
+        match self.stop_line(roads, intersections) {
+            Some(stop) => {
+                let mut obstacles = obstacles.to_vec();
+                obstacles.push(stop);
+                drive_towards(&mut vehicle, target, CRUISE_SPEED, &obstacles);
+            }
+            None => drive_towards(&mut vehicle, target, CRUISE_SPEED, obstacles),
+        }
+    }
+
+    /// The end of the current lane if the driver has to wait there for the lights to change.
+    /// Drivers on amber only stop if they are far enough away to do so, and never once on the crossing.
+    fn stop_line(&self, roads: &RoadGraph, intersections: &Intersections) -> Option<Vec2> {
+        let edge = roads.edge(self.edge);
+        let to_end = edge.length - self.distance;
+        if to_end < TILE_TEX_SIZE_SCALED / 2. {
+            return None;
+        }
+        let stop = match intersections.signal(roads.node(edge.to).tile, edge.end_direction.as_vector())? {
+            Signal::Green => false,
+            Signal::Amber => to_end > AMBER_STOP_DISTANCE,
+            Signal::Red => true,
+        };
+        stop.then(|| edge.point_at(edge.length).0)
+    }
+
+    /// Picks up the lane closest to where the vehicle is now, after it has left the road network.
+    pub fn rejoin_lanes(&mut self, roads: &RoadGraph, map: &Map, rng: &mut Rng) {
+        let vehicle = self.vehicle.borrow();
+        if let Some(point) = roads.nearest_lane(map, vehicle.position(), Some(vehicle.rotation)) {
+            self.edge = point.edge;
+            self.distance = point.distance;
+            self.next_edge = pick_turn(roads, point.edge, rng);
+        }
+    }
+
+    /// Tracks progress along the current edge and moves on to the next one once its end is reached.
+    fn advance(&mut self, roads: &RoadGraph, pos: Vec2, rng: &mut Rng) {
+        if let Some(point) = roads.nearest_lane_on(self.edge, pos) {
+            self.distance = point.distance;
+        }
+        let edge_length = roads.edge(self.edge).length;
+        if self.distance >= edge_length - LANE_OFFSET {
+            if let Some(next) = self.next_edge {
+                self.edge = next;
+                self.distance = 0.;
+                self.next_edge = pick_turn(roads, next, rng);
+            }
+        }
+    }
+
+    fn lane_point_ahead(&self, roads: &RoadGraph, lookahead: f32) -> Vec2 {
+        let edge = roads.edge(self.edge);
+        let ahead = self.distance + lookahead;
+        match self.next_edge {
+            Some(next) if ahead > edge.length => roads.edge(next).point_at(ahead - edge.length).0,
+            _ => edge.point_at(ahead).0,
+        }
+    }
+}
+
+/// Steers `vehicle` at `target`, slowing down for turns and for obstacles in its way.
+pub fn drive_towards(vehicle: &mut Vehicle, target: Vec2, cruise_speed: f32, obstacles: &[Vec2]) {
+    let pos = vehicle.position();
+    let heading = vehicle.rotation;
+    let angle = heading.angle_between((target - pos).normalize_or_zero());
+    vehicle.steer(angle / core::f32::consts::FRAC_PI_4);
+
+    let mut target_speed = if angle.abs() > core::f32::consts::FRAC_PI_8 {
+        TURN_SPEED.min(cruise_speed)
+    } else {
+        cruise_speed
+    };
+    if let Some(gap) = gap_ahead(pos, heading, obstacles, Vehicle::texture_size_scaled()) {
+        let missing = FOLLOWING_DISTANCE * 2. - gap;
+        if missing > 0. {
+            target_speed = (target_speed - missing * FOLLOWING_GAIN).max(0.);
+        }
+        if gap < FOLLOWING_DISTANCE {
+            target_speed = 0.;
+        }
+    }
+    set_speed(vehicle, target_speed);
+}
+
+/// Accelerates or brakes towards `target_speed`.
+pub fn set_speed(vehicle: &mut Vehicle, target_speed: f32) {
+    vehicle.throttle = if vehicle.velocity < target_speed - SPEED_TOLERANCE {
+        1.
+    } else if vehicle.velocity > target_speed + SPEED_TOLERANCE {
+        -1.
+    } else {
+        0.
+    };
+}
+
+/// A free lane position in the spawn ring around the player, as edge and distance along it.
+pub fn find_spawn_point(
+    roads: &RoadGraph,
+    map: &Map,
+    player_pos: Vec2,
+    vehicles: &[Rc<RefCell<Vehicle>>],
+    rng: &mut Rng,
+) -> Option<(EdgeId, f32)> {
+    (0..SPAWN_ATTEMPTS_PER_FRAME).find_map(|_| {
+        let angle = rng.gen_range(0., core::f32::consts::TAU);
+        let radius = rng.gen_range(SPAWN_RADIUS_MIN, SPAWN_RADIUS_MAX);
+        let candidate = player_pos + Vec2::from_angle(angle) * radius;
+        let edge = *rng.choose(roads.edges_at(map, candidate))?;
+        let point = roads.nearest_lane_on(edge, candidate)?;
+        let occupied = vehicles
+            .iter()
+            .any(|v| v.borrow().position().distance(point.pos) < SPAWN_CLEARANCE);
+        let visible = point.pos.distance(player_pos) < SPAWN_RADIUS_MIN;
+        (!occupied && !visible).then_some((point.edge, point.distance))
+    })
+}
+
+fn pick_turn(roads: &RoadGraph, edge: EdgeId, rng: &mut Rng) -> Option<EdgeId> {
+    rng.choose(&roads.edge(edge).turns).map(|turn| turn.to)
+}
+
+/// Distance from the front of a car at `pos` to the closest obstacle in its lane, if any.
+fn gap_ahead(pos: Vec2, heading: Vec2, obstacles: &[Vec2], length: f32) -> Option<f32> {
+    obstacles
+        .iter()
+        .filter_map(|obstacle| {
+            let relative = *obstacle - pos;
+            let ahead = relative.dot(heading);
+            let lateral = relative.perp_dot(heading).abs();
+            (ahead > 0. && lateral < LANE_HALF_WIDTH).then_some((ahead - length).max(0.))
+        })
+        .min_by(|a, b| a.total_cmp(b))
+}
+
+/// Ambient traffic around the player.
+#[derive(Default)]
+pub struct Traffic {
+    pub drivers: Vec<TrafficDriver>,
+    /// Vehicle models randomly picked from for newly spawned vehicles. Nothing spawns while this is empty.
+    pub vehicle_models: Vec<String>,
+}
+
+impl Traffic {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Drivers whose vehicle is too far from the player to matter. They are removed from the traffic.
+    pub fn take_far_away(&mut self, player_pos: Vec2) -> Vec<TrafficDriver> {
+        let (far, near) = self
+            .drivers
+            .drain(..)
+            .partition(|d| d.vehicle.borrow().position().distance(player_pos) > DESPAWN_RADIUS);
+        self.drivers = near;
+        far
+    }
+
+    /// Removes the driver of `vehicle` from the traffic, if it has one.
+    pub fn remove_driver(&mut self, vehicle: &Rc<RefCell<Vehicle>>) -> Option<TrafficDriver> {
+        let index = self
+            .drivers
+            .iter()
+            .position(|d| Rc::ptr_eq(&d.vehicle, vehicle))?;
+        Some(self.drivers.remove(index))
+    }
+
+    pub fn drive(
+        &mut self,
+        roads: &RoadGraph,
+        intersections: &Intersections,
+        vehicles: &[Rc<RefCell<Vehicle>>],
+        pedestrians: &[Vec2],
+        rng: &mut Rng,
+    ) {
+        for driver in &mut self.drivers {
+            let obstacles: Vec<Vec2> = vehicles
+                .iter()
+                .filter(|v| !Rc::ptr_eq(v, &driver.vehicle))
+                .map(|v| v.borrow().position())
+                .chain(pedestrians.iter().copied())
+                .collect();
+            driver.drive(roads, intersections, &obstacles, rng);
+        }
+    }
+}
@@ -0,0 +1,163 @@
+use std::{cell::RefCell, rc::Rc};
+
+use macroquad::prelude::*;
+
+use crate::{collide::Shape, draw::Draw, event::Entity, pedestrian::Pedestrian, vehicle::Vehicle};
+
+/// Tells zones apart, stays the same when other zones are removed.
+pub type ZoneId = u32;
+
+/// What a zone reacts to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Detects {
+    pub player: bool,
+    pub pedestrians: bool,
+    pub vehicles: bool,
+}
+
+impl Detects {
+    pub const PLAYER: Detects = Detects {
+        player: true,
+        pedestrians: false,
+        vehicles: false,
+    };
+    pub const ALL: Detects = Detects {
+        player: true,
+        pedestrians: true,
+        vehicles: true,
+    };
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ZoneEventKind {
+    Enter,
+    /// Sent every tick something stays inside, after the tick it entered.
+    Stay,
+    /// Also sent when something inside disappears from the world.
+    Exit,
+}
+
+#[derive(Clone)]
+pub struct ZoneEvent {
+    pub zone: ZoneId,
+    pub entity: Entity,
+    pub kind: ZoneEventKind,
+}
+
+/// An area in the world that notices the player, pedestrians or vehicles coming and going.
+pub struct TriggerZone {
+    pub id: ZoneId,
+    /// For whoever placed the zone to find it again, doesn't have to be unique.
+    pub name: String,
+    pub shape: Shape,
+    pub detects: Detects,
+    inside: Vec<Entity>,
+}
+
+impl TriggerZone {
+    pub fn contains(&self, entity: &Entity) -> bool {
+        self.inside.contains(entity)
+    }
+}
+
+/// All trigger zones in the world.
+#[derive(Default)]
+pub struct Triggers {
+    pub zones: Vec<TriggerZone>,
+    next_id: ZoneId,
+}
+
+impl Triggers {
+    pub fn add(&mut self, name: &str, shape: Shape, detects: Detects) -> ZoneId {
+        self.next_id += 1;
+        self.zones.push(TriggerZone {
+            id: self.next_id,
+            name: name.to_string(),
+            shape,
+            detects,
+            inside: Vec::new(),
+        });
+        self.next_id
+    }
+
+    /// Removes the zone without sending exit events for what was inside.
+    pub fn remove(&mut self, id: ZoneId) {
+        self.zones.retain(|zone| zone.id != id);
+    }
+
+    pub fn remove_named(&mut self, name: &str) {
+        self.zones.retain(|zone| zone.name != name);
+    }
+
+    pub fn zone(&self, id: ZoneId) -> Option<&TriggerZone> {
+        self.zones.iter().find(|zone| zone.id == id)
+    }
+
+    /// Works out what is inside every zone now, compared to the last update.
+    /// The player and pedestrians count as points, vehicles by their collision radius.
+    pub fn update(
+        &mut self,
+        player_pos: Vec2,
+        pedestrians: &[Pedestrian],
+        vehicles: &[Rc<RefCell<Vehicle>>],
+    ) -> Vec<ZoneEvent> {
+        let mut events = Vec::new();
+        for zone in &mut self.zones {
+            let mut now_inside = Vec::new();
+            if zone.detects.player && zone.shape.overlaps_circle(player_pos, 0.) {
+                now_inside.push(Entity::Player);
+            }
+            if zone.detects.pedestrians {
+                now_inside.extend(
+                    pedestrians
+                        .iter()
+                        .filter(|p| zone.shape.overlaps_circle(p.pos, 0.))
+                        .map(|p| Entity::Pedestrian(p.id)),
+                );
+            }
+            if zone.detects.vehicles {
+                now_inside.extend(
+                    vehicles
+                        .iter()
+                        .filter(|v| {
+                            let v = v.borrow();
+                            zone.shape.overlaps_circle(v.position(), v.collision_radius())
+                        })
+                        .map(|v| Entity::Vehicle(v.clone())),
+                );
+            }
+
+            for entity in &zone.inside {
+                if !now_inside.contains(entity) {
+                    events.push(ZoneEvent {
+                        zone: zone.id,
+                        entity: entity.clone(),
+                        kind: ZoneEventKind::Exit,
+                    });
+                }
+            }
+            for entity in &now_inside {
+                let kind = if zone.inside.contains(entity) {
+                    ZoneEventKind::Stay
+                } else {
+                    ZoneEventKind::Enter
+                };
+                events.push(ZoneEvent {
+                    zone: zone.id,
+                    entity: entity.clone(),
+                    kind,
+                });
+            }
+            zone.inside = now_inside;
+        }
+        events
+    }
+
+    /// Outlines of all zones, for placing them.
+    pub fn draw(&self, camera_pos: Vec2) {
+        let offset = Vec2::new(screen_width(), screen_height()) / 2. - camera_pos;
+        for zone in &self.zones {
+            zone.shape.draw_outline(offset, 2., Color::new(0., 1., 1., 0.6));
+        }
+    }
+}
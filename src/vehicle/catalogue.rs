@@ -0,0 +1,113 @@
+use std::{collections::HashMap, fs};
+
+use macroquad::prelude::*;
+use nanoserde::{DeJson, SerJson};
+
+use super::Vehicle;
+use crate::rng::Rng;
+
+/// Everything that makes one kind of vehicle different from another.
+#[derive(Debug, Clone, SerJson, DeJson)]
+pub struct VehicleModel {
+    /// What spawn code asks for, e.g. `"taxi"`.
+    pub name: String,
+    /// In kg.
+    pub mass: f32,
+    /// In Nm.
+    pub torque: f32,
+    pub reverse_torque: f32,
+    pub braking_torque: f32,
+    /// In m.
+    pub wheel_diameter: f32,
+    /// From first to top gear.
+    pub gear_ratios: Vec<f32>,
+    /// Length and width it takes up on the road, in world units.
+    pub footprint: [f32; 2],
+    /// Including the driver's.
+    pub seats: u8,
+    /// Path of the sprite, relative to the asset manifest.
+    pub sprite: String,
+    /// Driven around by ambient traffic.
+    #[nserde(default)]
+    pub traffic: bool,
+    /// Used by police units and roadblocks.
+    #[nserde(default)]
+    pub police: bool,
+}
+
+impl VehicleModel {
+    fn validate(&self) -> Result<(), String> {
+        if self.gear_ratios.is_empty() {
+            return Err(format!("{} has no gears", self.name));
+        }
+        if self.seats == 0 {
+            return Err(format!("{} has no seats", self.name));
+        }
+        if self.footprint.iter().any(|size| *size <= 0.) {
+            return Err(format!("{} has no footprint", self.name));
+        }
+        if self.mass <= 0. || self.wheel_diameter <= 0. {
+            return Err(format!("{} needs a positive mass and wheel diameter", self.name));
+        }
+        Ok(())
+    }
+}
+
+/// All vehicle models the game knows, with their sprites.
+#[derive(Clone, Default)]
+pub struct VehicleCatalogue {
+    models: Vec<VehicleModel>,
+    textures: HashMap<String, Texture2D>,
+}
+
+impl VehicleCatalogue {
+    /// Reads every model in `dir`, in file name order. `load_texture` is called once per sprite path.
+    pub fn load(
+        dir: &str,
+        mut load_texture: impl FnMut(&str) -> Result<Texture2D, String>,
+    ) -> Result<Self, String> {
+        let mut paths: Vec<_> = fs::read_dir(dir)
+            .map_err(|e| format!("Failed to read {dir}: {e}"))?
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+            .collect();
+        paths.sort();
+
+        let mut catalogue = VehicleCatalogue::default();
+        for path in paths {
+            let text = fs::read_to_string(&path).map_err(|e| format!("Failed to read {}: {e}", path.display()))?;
+            let model =
+                VehicleModel::deserialize_json(&text).map_err(|e| format!("Corrupt vehicle {}: {e}", path.display()))?;
+            model.validate()?;
+            if catalogue.model(&model.name).is_some() {
+                return Err(format!("Vehicle {} is defined twice", model.name));
+            }
+            if !catalogue.textures.contains_key(&model.sprite) {
+                let texture = load_texture(&model.sprite)?;
+                catalogue.textures.insert(model.sprite.clone(), texture);
+            }
+            catalogue.models.push(model);
+        }
+        Ok(catalogue)
+    }
+
+    pub fn model(&self, name: &str) -> Option<&VehicleModel> {
+        self.models.iter().find(|m| m.name == name)
+    }
+
+    pub fn models(&self) -> impl Iterator<Item = &VehicleModel> {
+        self.models.iter()
+    }
+
+    /// A new vehicle of the model called `name`.
+    pub fn spawn(&self, name: &str) -> Result<Vehicle, String> {
+        let model = self.model(name).ok_or_else(|| format!("Unknown vehicle model {name}"))?;
+        Ok(Vehicle::from_model(model, self.textures[&model.sprite].clone()))
+    }
+
+    /// A new vehicle of a model picked at random from `names`. Models can be listed more than once to make them more common.
+    pub fn spawn_random(&self, names: &[String], rng: &mut Rng) -> Option<Vehicle> {
+        let name = rng.choose(names)?;
+        self.spawn(name).map_err(|e| println!("{e}")).ok()
+    }
+}
@@ -31,11 +31,21 @@ impl Gearbox {
     
     pub fn shift_down(&mut self) -> Result<(), String> {
         if self.current_gear == 0 {
-            return Err(format!("Error shifting down when already in lowest gear"));
+            return Err("Error shifting down when already in lowest gear".to_string());
         }
         self.shift_to(self.current_gear - 1)
     }
 
+    /// A gearbox with the given ratios, from first to top gear.
+    /// `Panics` If there are no gears.
+    pub fn new(ratios: &[f32]) -> Self {
+        assert!(!ratios.is_empty(), "a gearbox needs gears");
+        Gearbox {
+            gears: ratios.iter().map(|ratio| Gear::new(*ratio)).collect(),
+            current_gear: 0,
+        }
+    }
+
     pub fn six_step() -> Self {
         Gearbox {
             gears: vec![
@@ -1,18 +1,34 @@
+pub mod catalogue;
 pub mod gearbox;
 
-use core::{f32, f64};
+use core::f32;
+use catalogue::VehicleModel;
 use gearbox::Gearbox;
 use macroquad::prelude::*;
 
 use crate::{
-    collide::{Collide}, controller::ControllerDirectionState, draw::Draw, Direction, Update,
+    collide::{Collide, LineSegment}, draw::Draw, Direction, Update,
 };
 
 pub struct Vehicle {
     texture: Texture2D,
-    pub bounding_box: Rect,
+    /// Name of the model in the vehicle catalogue.
+    pub model: String,
+    /// Size of the footprint on the road.
+    pub length: f32,
+    pub width: f32,
     pos: Vec2,
-    pub entered: bool,
+    pub driver: Option<Occupant>,
+    /// Who sits in each of the passenger seats.
+    pub passengers: Vec<Option<Occupant>>,
+    pub locked: bool,
+    /// Seconds the alarm keeps going off.
+    pub alarm: f32,
+    pub siren: bool,
+    /// The player has driven this vehicle, so it is kept around and saved with the game.
+    pub owned: bool,
+    /// Accumulated body damage, 0 is pristine.
+    pub damage: f32,
     pub acceleration: f32,
     //pub wheel_base: f32,
     pub turning_angle: f32,
@@ -23,6 +39,8 @@ pub struct Vehicle {
     pub velocity: f32,
     pub rotation: Vec2,
     pub throttle: f32,
+    /// Locks the wheels, braking regardless of the throttle.
+    pub handbrake: bool,
     pub breaking_torque: f32,
     pub wheel_diameter: f32,
     pub rpm: f32,
@@ -33,17 +51,72 @@ pub const TEX_SIZE: f32 = 32.;
 pub const SCALING_FAC: f32 = 8.;
 pub const DRAG_COEFFICIENT: f32 = 100.; // negative acceleration
 pub const FRICTION_FORCE: f32 = 3000.;
+/// Distance of the doors from the vehicle's side, where the player stands to get in.
+pub const DOOR_CLEARANCE: f32 = 25.;
+/// Distance of the doors in front of the vehicle's center.
+pub const DOOR_FORWARD_OFFSET: f32 = 10.;
+pub const ALARM_DURATION: f32 = 10.;
+/// The engine never turns slower than this, or faster than `REDLINE_RPM`.
+pub const IDLE_RPM: f32 = 800.;
+pub const REDLINE_RPM: f32 = 7000.;
+/// Damage per unit of closing speed in a crash.
+pub const COLLISION_DAMAGE: f32 = 2.;
+/// How strongly being hit off center spins a vehicle around.
+const COLLISION_SPIN: f32 = 0.0004;
+const MAX_COLLISION_SPIN: f32 = core::f32::consts::FRAC_PI_4;
+/// How far from straight out of the side window a shot from inside can go, either way.
+pub const WINDOW_ARC: f32 = core::f32::consts::FRAC_PI_3;
+/// With screen relative controls, holding a direction further than this from the front backs the vehicle up.
+const REVERSE_ANGLE: f32 = 3. * core::f32::consts::FRAC_PI_4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Occupant {
+    Player,
+    Npc,
+}
+
+/// A place to sit in a vehicle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Seat {
+    Driver,
+    /// Index into `Vehicle::passengers`.
+    Passenger(usize),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Door {
+    Left,
+    Right,
+}
+
+impl Door {
+    pub fn opposite(&self) -> Door {
+        match self {
+            Door::Left => Door::Right,
+            Door::Right => Door::Left,
+        }
+    }
+}
 
 impl Vehicle {
     pub fn new(texture: Texture2D) -> Self {
-        texture.set_filter(FilterMode::Nearest);
         Vehicle {
             texture,
+            model: "sedan".to_string(),
+            length: 180.,
+            width: 110.,
             acceleration: 0.,
             pos: (0., 0.).into(),
-            entered: false,
+            driver: None,
+            passengers: vec![None; 3],
+            locked: false,
+            alarm: 0.,
+            siren: false,
+            owned: false,
+            damage: 0.,
             //wheel_base: 256.,
             throttle: 0.,
+            handbrake: false,
             turning_angle: 0., // 45 degrees
             rotation: Vec2::new(0., -1.).normalize(),
             torque: 650., // 650Nm
@@ -55,15 +128,29 @@ impl Vehicle {
             wheel_diameter: 0.4,
             gearbox: Gearbox::six_step(),
             rpm: 0.,
-            bounding_box: Rect::new(
-                0.,
-                0.,
-                Vehicle::texture_size_scaled(),
-                Vehicle::texture_size_scaled(),
-            ), //TODO: adjust
         }
     }
 
+    pub fn from_model(model: &VehicleModel, texture: Texture2D) -> Self {
+        let mut vehicle = Vehicle::new(texture);
+        vehicle.model = model.name.clone();
+        vehicle.mass = model.mass;
+        vehicle.torque = model.torque;
+        vehicle.reverse_torque = model.reverse_torque;
+        vehicle.breaking_torque = model.braking_torque;
+        vehicle.wheel_diameter = model.wheel_diameter;
+        vehicle.gearbox = Gearbox::new(&model.gear_ratios);
+        vehicle.length = model.footprint[0];
+        vehicle.width = model.footprint[1];
+        vehicle.passengers = vec![None; model.seats as usize - 1];
+        vehicle
+    }
+
+    /// Vehicles are treated as circles of this radius when they crash into each other.
+    pub fn collision_radius(&self) -> f32 {
+        self.length / 2.
+    }
+
     pub fn steer_right(&mut self) {
         self.turning_angle = core::f32::consts::FRAC_PI_4;
     }
@@ -76,6 +163,98 @@ impl Vehicle {
         self.turning_angle = 0.;
     }
 
+    /// Steers proportionally, from -1 (full left) to 1 (full right).
+    pub fn steer(&mut self, amount: f32) {
+        self.turning_angle = amount.clamp(-1., 1.) * core::f32::consts::FRAC_PI_4;
+    }
+
+    pub fn set_position(&mut self, pos: Vec2) {
+        self.pos = pos;
+    }
+
+    pub fn door_position(&self, door: Door) -> Vec2 {
+        let right = Vec2::new(-self.rotation.y, self.rotation.x);
+        let side = match door {
+            Door::Left => -1.,
+            Door::Right => 1.,
+        };
+        self.pos + right * side * (self.width / 2. + DOOR_CLEARANCE) + self.rotation * DOOR_FORWARD_OFFSET
+    }
+
+    /// The door closest to `pos` and its distance.
+    pub fn nearest_door(&self, pos: Vec2) -> (Door, f32) {
+        [Door::Left, Door::Right]
+            .into_iter()
+            .map(|door| (door, self.door_position(door).distance(pos)))
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .unwrap()
+    }
+
+    pub fn occupant(&self, seat: Seat) -> Option<Occupant> {
+        match seat {
+            Seat::Driver => self.driver,
+            Seat::Passenger(i) => self.passengers.get(i).copied().flatten(),
+        }
+    }
+
+    pub fn set_occupant(&mut self, seat: Seat, occupant: Option<Occupant>) {
+        match seat {
+            Seat::Driver => self.driver = occupant,
+            Seat::Passenger(i) => self.passengers[i] = occupant,
+        }
+    }
+
+    /// The door next to `seat`. The driver sits on the left, passengers alternate starting on the right.
+    pub fn seat_door(seat: Seat) -> Door {
+        match seat {
+            Seat::Passenger(i) if i % 2 == 0 => Door::Right,
+            _ => Door::Left,
+        }
+    }
+
+    /// Where someone sitting in `seat` can shoot when aiming at `aim`:
+    /// out of the window next to them, turned as far towards the aim as the window allows.
+    pub fn aim_from_window(&self, seat: Seat, aim: Vec2) -> Vec2 {
+        let right = Vec2::new(-self.rotation.y, self.rotation.x);
+        let out = match Self::seat_door(seat) {
+            Door::Left => -right,
+            Door::Right => right,
+        };
+        let angle = out.angle_between(aim).clamp(-WINDOW_ARC, WINDOW_ARC);
+        out.rotate(Vec2::from_angle(angle))
+    }
+
+    /// An empty passenger seat, preferably one next to `door`.
+    pub fn free_passenger_seat(&self, door: Door) -> Option<Seat> {
+        let free: Vec<Seat> = (0..self.passengers.len())
+            .map(Seat::Passenger)
+            .filter(|seat| self.occupant(*seat).is_none())
+            .collect();
+        free.iter()
+            .find(|seat| Self::seat_door(**seat) == door)
+            .or(free.first())
+            .copied()
+    }
+
+    /// Empties the passenger seats taken by NPCs, returning how many got out.
+    pub fn take_npc_passengers(&mut self) -> usize {
+        let mut count = 0;
+        for seat in self.passengers.iter_mut().filter(|p| **p == Some(Occupant::Npc)) {
+            *seat = None;
+            count += 1;
+        }
+        count
+    }
+
+    pub fn trigger_alarm(&mut self) {
+        self.alarm = ALARM_DURATION;
+    }
+
+    /// Velocity as a vector, taking the direction of travel into account.
+    pub fn velocity_vector(&self) -> Vec2 {
+        self.rotation * self.velocity * if self.reversed { -1. } else { 1. }
+    }
+
     pub fn apply_force(&mut self, force: f32, time: f32) {
         // a = F/m
         self.acceleration = force / self.mass;
@@ -129,11 +308,36 @@ impl Vehicle {
             }
         }
     }
+
+    /// Drives toward `direction` on screen with the given throttle, turning the front towards it,
+    /// or the rear when it points behind the vehicle.
+    pub fn handle_screen_controls(&mut self, direction: Option<Vec2>, throttle: f32) {
+        let Some(direction) = direction.filter(|d| *d != Vec2::ZERO) else {
+            self.throttle = 0.;
+            self.steer_neutral();
+            return;
+        };
+        let angle = self.rotation.angle_between(direction);
+        if angle.abs() <= REVERSE_ANGLE {
+            self.throttle = throttle;
+            self.steer(angle / core::f32::consts::FRAC_PI_4);
+        } else {
+            // Turning the wheels turns the front and the rear the same way while backing up
+            self.throttle = -throttle;
+            self.steer((-self.rotation).angle_between(direction) / core::f32::consts::FRAC_PI_4);
+        }
+    }
 }
 
 impl Collide for Vehicle {
-    fn collision_shape(&self) -> Vec<crate::collide::LineSegment> {
-        self.bounding_box.collision_shape()
+    /// The footprint, turned the way the vehicle faces.
+    fn collision_shape(&self) -> Vec<LineSegment> {
+        let forward = self.rotation * self.length / 2.;
+        let right = self.rotation.perp() * self.width / 2.;
+        let corners = [forward + right, forward - right, -forward - right, -forward + right].map(|c| self.pos + c);
+        (0..corners.len())
+            .map(|i| LineSegment::new(corners[i], corners[(i + 1) % corners.len()]))
+            .collect()
     }
 }
 
@@ -165,6 +369,27 @@ impl Draw for Vehicle {
                 ..Default::default()
             },
         );
+        if self.siren {
+            let center = screen_pos + Vec2::splat(Self::texture_size_scaled() / 2.);
+            let right = Vec2::new(-self.rotation.y, self.rotation.x) * 20.;
+            let blue_on = (get_time() * 6.) as i32 % 2 == 0;
+            draw_circle(center.x - right.x, center.y - right.y, 10., if blue_on { BLUE } else { MAROON });
+            draw_circle(center.x + right.x, center.y + right.y, 10., if blue_on { DARKBLUE } else { RED });
+        }
+        // Indicators flash twice a second while the alarm is going off
+        if self.alarm > 0. && (self.alarm * 4.) as i32 % 2 == 0 {
+            let center = screen_pos + Vec2::splat(Self::texture_size_scaled() / 2.);
+            let right = Vec2::new(-self.rotation.y, self.rotation.x);
+            for corner in [
+                self.rotation + right,
+                self.rotation - right,
+                -self.rotation + right,
+                -self.rotation - right,
+            ] {
+                let light = center + corner * self.width * 0.6;
+                draw_circle(light.x, light.y, 12., ORANGE);
+            }
+        }
     }
 
     fn position(&self) -> Vec2 {
@@ -173,15 +398,16 @@ impl Draw for Vehicle {
 }
 
 impl Update for Vehicle {
-    fn update(&mut self) {
+    fn update(&mut self, dt: f32) {
+        self.alarm = (self.alarm - dt).max(0.);
         let drag_force = drag_force(
             1.293, // air density
             self.velocity,
             1.3, // reference area
             0.4, // drag coefficient
         );
-        self.apply_force(-drag_force, get_frame_time());
-        self.apply_force(-FRICTION_FORCE, get_frame_time());
+        self.apply_force(-drag_force, dt);
+        self.apply_force(-FRICTION_FORCE, dt);
 
         if self.throttle > 0. {
             self.reversed = false;
@@ -189,7 +415,7 @@ impl Update for Vehicle {
                 self.force_from_wheel_torque(
                     self.throttle * self.torque * self.gearbox.current_gear().ratio,
                 ),
-                get_frame_time(),
+                dt,
             );
             // print_velocity(self.velocity, "Accelerating!");
         } else {
@@ -199,30 +425,74 @@ impl Update for Vehicle {
             if self.reversed {
                 self.apply_force(
                     self.force_from_wheel_torque(self.throttle * self.reverse_torque),
-                    get_frame_time(),
+                    dt,
                 );
                 // print_velocity(self.velocity, "Reversing!");
             } else {
                 self.apply_force(
                     self.force_from_wheel_torque(self.throttle * self.breaking_torque),
-                    get_frame_time(),
+                    dt,
                 );
                 // print_velocity(self.velocity, "Braking!");
             }
         }
+        if self.handbrake {
+            self.apply_force(-self.force_from_wheel_torque(self.breaking_torque), dt);
+        }
         // F = m * a, a = F/m
         self.rotation = self
             .rotation
-            .rotate(Vec2::from_angle((self.turning_angle * 0.03) as f32));
+            .rotate(Vec2::from_angle(self.turning_angle * 0.03));
 
         self.pos += self.rotation * self.velocity * if self.reversed { -1. } else { 1. };
+
+        let wheel_rpm = self.velocity / (core::f32::consts::PI * self.wheel_diameter) * 60.;
+        self.rpm = (wheel_rpm * self.gearbox.gear_ratio()).clamp(IDLE_RPM, REDLINE_RPM);
     }
 }
 
+#[allow(dead_code)]
 fn print_velocity(velocity: f32, tag: &str) {
     println!("{} {}m/s {}km/h", tag, velocity, velocity * 3.6);
 }
 
+/// Pushes two overlapping vehicles apart. Both get damaged and slowed down by the crash,
+/// and a hit that is off center spins the vehicle that got hit, which is what makes a PIT manoeuvre work.
+/// Returns the closing speed if they crashed.
+pub fn collide(a: &mut Vehicle, b: &mut Vehicle) -> Option<f32> {
+    let offset = b.pos - a.pos;
+    let distance = offset.length();
+    let reach = a.collision_radius() + b.collision_radius();
+    if distance >= reach || distance == 0. {
+        return None;
+    }
+    let normal = offset / distance;
+    let overlap = reach - distance;
+    a.pos -= normal * overlap / 2.;
+    b.pos += normal * overlap / 2.;
+
+    let closing_speed = (a.velocity_vector() - b.velocity_vector()).dot(normal);
+    if closing_speed <= 0. {
+        return None;
+    }
+    a.damage += closing_speed * COLLISION_DAMAGE;
+    b.damage += closing_speed * COLLISION_DAMAGE;
+    let spin_b = collision_spin(a, b);
+    let spin_a = collision_spin(b, a);
+    a.rotation = a.rotation.rotate(Vec2::from_angle(spin_a));
+    b.rotation = b.rotation.rotate(Vec2::from_angle(spin_b));
+    a.velocity *= 0.7;
+    b.velocity *= 0.7;
+    Some(closing_speed)
+}
+
+/// Rotation of `hit` caused by `hitter` pushing along its direction of travel at the contact point.
+fn collision_spin(hitter: &Vehicle, hit: &Vehicle) -> f32 {
+    let lever = (hitter.pos - hit.pos) / 2.;
+    let torque = lever.perp_dot(hitter.velocity_vector());
+    (torque * COLLISION_SPIN).clamp(-MAX_COLLISION_SPIN, MAX_COLLISION_SPIN)
+}
+
 fn drag_force(
     mass_density: f32,
     flow_velocity: f32,
@@ -0,0 +1,140 @@
+pub const MAX_STARS: u8 = 6;
+/// Heat needed for each number of stars, starting at one star.
+const STAR_THRESHOLDS: [f32; MAX_STARS as usize] = [40., 150., 300., 500., 800., 1200.];
+/// Seconds the player has to stay out of sight to lose a star, before adding `SEARCH_TIME_PER_STAR`.
+const SEARCH_TIME: f32 = 6.;
+const SEARCH_TIME_PER_STAR: f32 = 3.;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Crime {
+    ShotFired,
+    VehicleTheft,
+    PedestrianHit,
+    RanRedLight,
+}
+
+impl Crime {
+    fn heat(&self) -> f32 {
+        match self {
+            Crime::ShotFired => 25.,
+            Crime::VehicleTheft => 60.,
+            Crime::PedestrianHit => 45.,
+            Crime::RanRedLight => 15.,
+        }
+    }
+
+    /// Whether the crime only counts when the police see it happen.
+    /// Gunfire is heard everywhere and running someone over always has witnesses.
+    pub fn needs_police_witness(&self) -> bool {
+        matches!(self, Crime::VehicleTheft | Crime::RanRedLight)
+    }
+}
+
+/// How the police go about catching the player at a wanted level.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Tactics {
+    /// Police cars around the player.
+    pub units: usize,
+    /// Units drive after the player instead of patrolling.
+    pub pursue: bool,
+    /// Units try to ram the player off the road.
+    pub ram: bool,
+    /// Roadblocks are set up on the player's way.
+    pub roadblocks: bool,
+    /// Officers leave their cars and shoot at the player.
+    pub shoot: bool,
+}
+
+impl Tactics {
+    pub fn for_stars(stars: u8) -> Self {
+        Tactics {
+            units: match stars {
+                0 | 1 => 2,
+                2 => 3,
+                3 => 4,
+                4 => 5,
+                _ => 6,
+            },
+            pursue: stars >= 1,
+            ram: stars >= 3,
+            roadblocks: stars >= 4,
+            shoot: stars >= 2,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct WantedLevel {
+    heat: f32,
+    stars: u8,
+    /// Seconds since the police last saw the player.
+    time_unseen: f32,
+}
+
+impl WantedLevel {
+    pub fn stars(&self) -> u8 {
+        self.stars
+    }
+
+    pub fn tactics(&self) -> Tactics {
+        Tactics::for_stars(self.stars)
+    }
+
+    /// Whether the police are currently searching for the player without seeing them,
+    /// i.e. the player is about to lose a star if they stay hidden.
+    pub fn is_searching(&self) -> bool {
+        self.stars > 0 && self.time_unseen > 0.
+    }
+
+    /// Registers a crime. Returns the new number of stars if it changed.
+    pub fn commit(&mut self, crime: Crime, seen_by_police: bool) -> Option<u8> {
+        if crime.needs_police_witness() && !seen_by_police {
+            return None;
+        }
+        self.heat = (self.heat + crime.heat()).min(STAR_THRESHOLDS[MAX_STARS as usize - 1]);
+        self.time_unseen = 0.;
+        self.refresh_stars()
+    }
+
+    /// Stars drop one by one while the police can't see the player.
+    /// Returns the new number of stars if it changed.
+    pub fn update(&mut self, seen_by_police: bool, dt: f32) -> Option<u8> {
+        if self.stars == 0 {
+            return None;
+        }
+        if seen_by_police {
+            self.time_unseen = 0.;
+            return None;
+        }
+        self.time_unseen += dt;
+        if self.time_unseen < SEARCH_TIME + SEARCH_TIME_PER_STAR * self.stars as f32 {
+            return None;
+        }
+        self.time_unseen = 0.;
+        self.heat = match self.stars {
+            1 => 0.,
+            stars => STAR_THRESHOLDS[stars as usize - 2],
+        };
+        self.refresh_stars()
+    }
+
+    /// Sets the wanted level directly, e.g. from a cheat, a mission or a loaded save.
+    pub fn set_stars(&mut self, stars: u8) {
+        let stars = stars.min(MAX_STARS);
+        self.heat = match stars {
+            0 => 0.,
+            stars => STAR_THRESHOLDS[stars as usize - 1],
+        };
+        self.time_unseen = 0.;
+        self.stars = stars;
+    }
+
+    fn refresh_stars(&mut self) -> Option<u8> {
+        let stars = STAR_THRESHOLDS.iter().filter(|t| self.heat >= **t).count() as u8;
+        if stars == self.stars {
+            return None;
+        }
+        self.stars = stars;
+        Some(stars)
+    }
+}
@@ -1,34 +1,104 @@
 use std::{cell::RefCell, rc::Rc};
 
 use macroquad::prelude::*;
+use nanoserde::{DeJson, SerJson};
 
-use crate::{collide::Collide, draw::Draw, vehicle::{self, Vehicle}};
+use crate::{collide::Collide, draw::Draw, vehicle::Vehicle};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, SerJson, DeJson)]
+pub enum WeaponKind {
+    Pistol,
+    Smg,
+    Shotgun,
+}
+
+impl WeaponKind {
+    pub fn fire_cooldown_seconds(&self) -> f32 {
+        match self {
+            WeaponKind::Pistol => 2.,
+            WeaponKind::Smg => 0.1,
+            WeaponKind::Shotgun => 3.,
+        }
+    }
+
+    /// Rounds a newly picked up weapon comes with.
+    pub fn starting_ammo(&self) -> u32 {
+        match self {
+            WeaponKind::Pistol => 60,
+            WeaponKind::Smg => 120,
+            WeaponKind::Shotgun => 24,
+        }
+    }
+
+    /// Damage done to whoever gets hit.
+    pub fn damage(&self) -> f32 {
+        match self {
+            WeaponKind::Pistol => 40.,
+            WeaponKind::Smg => 15.,
+            WeaponKind::Shotgun => 100.,
+        }
+    }
+
+    /// Small enough to be fired out of a car window.
+    pub fn drive_by(&self) -> bool {
+        match self {
+            WeaponKind::Pistol | WeaponKind::Smg => true,
+            WeaponKind::Shotgun => false,
+        }
+    }
+
+    /// The kind called `name` in scripts, like `"smg"`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "pistol" => Some(WeaponKind::Pistol),
+            "smg" => Some(WeaponKind::Smg),
+            "shotgun" => Some(WeaponKind::Shotgun),
+            _ => None,
+        }
+    }
+}
 
 pub struct Weapon {
+    pub kind: WeaponKind,
     pub texture: Texture2D,
     pub fire_cooldown_seconds: f32,
     pub pos: Vec2,
+    pub ammo: u32,
 }
 
 impl Weapon {
-    pub fn new(texture: Texture2D, fire_cooldown_seconds: f32) -> Self {
-        texture.set_filter(FilterMode::Nearest);
+    pub fn new(kind: WeaponKind, texture: Texture2D) -> Self {
         Self {
+            kind,
             texture,
-            fire_cooldown_seconds,
+            fire_cooldown_seconds: kind.fire_cooldown_seconds(),
             pos: (0., 0.).into(),
+            ammo: kind.starting_ammo(),
         }
     }
 
-    pub fn shoot(&self, direction: Vec2, objects: &[Rc<RefCell<(dyn Collide + 'static)>>]) -> Option<Rc<RefCell<dyn Collide>>> {
-        let projectile = Projectile::new(self.pos, direction);
-        for object in objects {
-            let object = object.borrow();
-            if let Some((t1, normal)) = projectile.check_collision(&*object) {
-                println!("Collision");
-            }
+    /// Fires one round, if there is any left. Returns the closest object hit and how far away it is.
+    /// The round passes through `shooter_vehicle`, the car the shot is fired from.
+    pub fn shoot(
+        &mut self,
+        direction: Vec2,
+        objects: &[Rc<RefCell<dyn Collide>>],
+        shooter_vehicle: Option<&Rc<RefCell<Vehicle>>>,
+    ) -> Option<(Rc<RefCell<dyn Collide>>, f32)> {
+        if self.ammo == 0 {
+            return None;
         }
-        None
+        self.ammo -= 1;
+        let projectile = Projectile::new(self.pos, direction);
+        objects
+            .iter()
+            .filter(|object| !shooter_vehicle.is_some_and(|v| std::ptr::addr_eq(Rc::as_ptr(object), Rc::as_ptr(v))))
+            .filter_map(|object| {
+                let (distance, _normal) = projectile.check_collision(&*object.borrow())?;
+                Some((object, distance))
+            })
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(object, distance)| (object.clone(), distance))
     }
 }
 